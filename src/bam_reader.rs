@@ -0,0 +1,244 @@
+use std::io::{ErrorKind, Read};
+
+use crate::{
+    bgzf::BgzfReader,
+    error::{BioReaderError, RecordPosition},
+    sam_header::{HeaderFields, ReferenceSequence, SamHeader},
+    sequence::bam_record::RefBamRecord,
+};
+
+const MAGIC: &[u8; 4] = b"BAM\x01";
+
+/// Reads BAM files record by record.
+///
+/// Records borrow the internal buffer of the reader and are only valid until
+/// the next call of [`BamReader::next`]. Byte offsets of errors count
+/// decompressed bytes.
+pub struct BamReader<R: Read> {
+    // Decompressed BAM data
+    reader: R,
+    header: SamHeader,
+    buffer: Vec<u8>,
+    // Position of the next record
+    next_position: RecordPosition,
+}
+
+impl<R: Read> BamReader<BgzfReader<R>> {
+    pub fn new(reader: R) -> Result<Self, BioReaderError> {
+        Self::with_threads(reader, 1)
+    }
+
+    /// Decompress BGZF blocks on `num_threads` threads
+    pub fn with_threads(reader: R, num_threads: usize) -> Result<Self, BioReaderError> {
+        Self::from_decompressed(BgzfReader::with_threads(reader, num_threads))
+    }
+}
+
+impl<R: Read> BamReader<R> {
+    /// Read BAM data that was already decompressed, e.g. by [`crate::compression::decode`]
+    pub fn from_decompressed(reader: R) -> Result<Self, BioReaderError> {
+        let mut br = Self {
+            reader,
+            header: SamHeader::default(),
+            buffer: Vec::new(),
+            next_position: RecordPosition::default(),
+        };
+        br.read_header()?;
+        Ok(br)
+    }
+
+    pub fn header(&self) -> &SamHeader {
+        &self.header
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>, BioReaderError> {
+        let mut bytes = vec![0u8; n];
+        self.reader.read_exact(&mut bytes)?;
+        self.next_position.byte_offset += n as u64;
+        Ok(bytes)
+    }
+
+    fn read_len(&mut self) -> Result<usize, BioReaderError> {
+        let position = self.next_position;
+        let bytes = self.read_bytes(4)?;
+        let len = i32::from_le_bytes(bytes.try_into().expect("Read 4 bytes"));
+        usize::try_from(len).map_err(|_| BioReaderError::InvalidHeader {
+            reason: "Negative length in BAM header".to_string(),
+            position,
+        })
+    }
+
+    fn read_header(&mut self) -> Result<(), BioReaderError> {
+        if self.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(BioReaderError::InvalidHeader {
+                reason: "Not a BAM file".to_string(),
+                position: RecordPosition::default(),
+            });
+        }
+
+        let text_len = self.read_len()?;
+        let text_position = self.next_position;
+        let text = self.read_bytes(text_len)?;
+        // The text may be padded with NULs
+        let text_end = memchr::memchr(0, &text).unwrap_or(text.len());
+        self.header = SamHeader::parse_at(&text[..text_end], text_position)?;
+
+        // The binary reference list is authoritative for reference ids
+        let n_ref = self.read_len()?;
+        let mut references = Vec::with_capacity(n_ref);
+        for _ in 0..n_ref {
+            let name_len = self.read_len()?;
+            let mut name = self.read_bytes(name_len)?;
+            name.pop_if(|c| *c == 0);
+            let length = self.read_len()?;
+
+            let reference = match self.header.references.iter().find(|r| r.name() == name) {
+                Some(reference) => reference.clone(),
+                None => {
+                    let mut fields = HeaderFields::default();
+                    fields.set(*b"SN", name);
+                    fields.set(*b"LN", length.to_string().into_bytes());
+                    ReferenceSequence { fields }
+                }
+            };
+            references.push(reference);
+        }
+        self.header.references = references;
+
+        Ok(())
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<RefBamRecord<'_>>, BioReaderError> {
+        let position = self.next_position;
+        let truncated = || BioReaderError::TruncatedRecord { position };
+
+        // Distinguish the end of the file from a truncated block_size
+        let mut size = [0u8; 4];
+        let mut filled = 0;
+        while filled < size.len() {
+            match self.reader.read(&mut size[filled..])? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(truncated()),
+                n => filled += n,
+            }
+        }
+
+        let block_size = i32::from_le_bytes(size);
+        let block_size = usize::try_from(block_size).map_err(|_| BioReaderError::InvalidRecord {
+            reason: "Negative BAM record size".to_string(),
+            position,
+        })?;
+        self.buffer.resize(block_size, 0);
+        self.reader.read_exact(&mut self.buffer).map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => truncated(),
+            _ => err.into(),
+        })?;
+        self.next_position.advance(size.len() + block_size, 1);
+
+        RefBamRecord::new(&self.buffer, position).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bgzf::tests::bgzf,
+        sam_writer::format_record,
+        sequence::{fastq_record::LineEnding, sam_record::AlignmentRecord},
+    };
+
+    fn bam() -> Vec<u8> {
+        bam_with_tags(b"NMC\x01XZZhi\0ZBBs\x02\0\0\0\xff\xff\x02\0")
+    }
+
+    fn bam_with_tags(tags: &[u8]) -> Vec<u8> {
+        let text = b"@HD\tVN:1.6\tSO:unsorted\n@SQ\tSN:chr1\tLN:1000\n";
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&(text.len() as i32).to_le_bytes());
+        data.extend_from_slice(text);
+        data.extend_from_slice(&2i32.to_le_bytes());
+        for (name, length) in [(&b"chr1\0"[..], 1000i32), (&b"chr2\0"[..], 500)] {
+            data.extend_from_slice(&(name.len() as i32).to_le_bytes());
+            data.extend_from_slice(name);
+            data.extend_from_slice(&length.to_le_bytes());
+        }
+
+        let mut record = Vec::new();
+        record.extend_from_slice(&0i32.to_le_bytes()); // refID
+        record.extend_from_slice(&6i32.to_le_bytes()); // pos
+        record.push(5); // l_read_name
+        record.push(30); // mapq
+        record.extend_from_slice(&0u16.to_le_bytes()); // bin
+        record.extend_from_slice(&2u16.to_le_bytes()); // n_cigar_op
+        record.extend_from_slice(&99u16.to_le_bytes()); // flag
+        record.extend_from_slice(&5i32.to_le_bytes()); // l_seq
+        record.extend_from_slice(&1i32.to_le_bytes()); // next_refID
+        record.extend_from_slice(&36i32.to_le_bytes()); // next_pos
+        record.extend_from_slice(&39i32.to_le_bytes()); // tlen
+        record.extend_from_slice(b"r001\0");
+        record.extend_from_slice(&(3u32 << 4).to_le_bytes()); // 3M
+        record.extend_from_slice(&((2u32 << 4) | 1).to_le_bytes()); // 2I
+        record.extend_from_slice(&[0x12, 0x48, 0xf0]); // ACGTN
+        record.extend_from_slice(&[30, 30, 31, 32, 2]);
+        record.extend_from_slice(tags);
+
+        for _ in 0..3 {
+            data.extend_from_slice(&(record.len() as i32).to_le_bytes());
+            data.extend_from_slice(&record);
+        }
+        bgzf(&data)
+    }
+
+    #[test]
+    fn test_bam_reader() {
+        let compressed = bam();
+        for threads in [1, 3] {
+            let mut reader = BamReader::with_threads(&compressed[..], threads).unwrap();
+            let header = reader.header().clone();
+            assert_eq!(header.references.len(), 2);
+            assert_eq!(header.references[1].name(), b"chr2");
+            assert_eq!(header.references[1].length(), 500);
+
+            let mut count = 0;
+            while let Some(record) = reader.next().unwrap() {
+                count += 1;
+                assert_eq!(record.qname(), b"r001");
+                assert_eq!(record.reference_name(&header), Some(&b"chr1"[..]));
+
+                let mut line = Vec::new();
+                format_record(&record.to_sam_record(&header).unwrap(), LineEnding::Unix, &mut line);
+                assert_eq!(
+                    String::from_utf8(line).unwrap(),
+                    "r001\t99\tchr1\t7\t30\t3M2I\tchr2\t37\t39\tACGTN\t??@A#\tNM:i:1\tXZ:Z:hi\tZB:B:s,-1,2\n"
+                );
+            }
+            assert_eq!(count, 3);
+        }
+    }
+
+    #[test]
+    fn test_invalid_tag() {
+        // Tag type 'Q' does not exist
+        let compressed = bam_with_tags(b"NMC\x01XXQ\x01");
+        let mut reader = BamReader::new(&compressed[..]).unwrap();
+        let record = reader.next().unwrap().unwrap();
+        let err = record.sam_tags().unwrap_err();
+        assert!(matches!(err, BioReaderError::InvalidRecord { position: RecordPosition { byte_offset: 81, record_number: 1 }, .. }), "{err}");
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&40i32.to_le_bytes());
+        data.extend_from_slice(&[0; 10]);
+        let compressed = bgzf(&data);
+
+        let mut reader = BamReader::new(&compressed[..]).unwrap();
+        let err = reader.next().unwrap_err();
+        assert!(matches!(err, BioReaderError::TruncatedRecord { position: RecordPosition { byte_offset: 12, record_number: 1 } }), "{err}");
+        assert!(matches!(BamReader::new(&bgzf(b"BAM\x02")[..]), Err(BioReaderError::InvalidHeader { .. })));
+    }
+}
@@ -0,0 +1,424 @@
+use std::{
+    collections::BTreeMap,
+    io::{Error, ErrorKind, Read, Write},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+use flate2::{write::DeflateEncoder, Crc, Decompress, FlushDecompress, Status};
+
+// Fixed part of the gzip header of a BGZF block, up to and including XLEN
+const HEADER_SIZE: usize = 12;
+// CRC32 and ISIZE at the end of every block
+const FOOTER_SIZE: usize = 8;
+// Blocks queued per decompression thread
+const BLOCKS_PER_THREAD: usize = 4;
+// Uncompressed bytes per written block, as in htslib, so that even incompressible data fits into 64 KiB
+const MAX_BLOCK_DATA: usize = 0xff00;
+const MAX_BLOCK_SIZE: usize = 1 << 16;
+// Gzip header with the BC extra field, followed by the block size - 1
+const BLOCK_HEADER: [u8; 16] = [0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0];
+
+/// Whether the bytes start with a gzip header carrying the BGZF `BC` extra field
+pub fn is_bgzf(bytes: &[u8]) -> bool {
+    bytes.len() >= 16
+        && bytes[..4] == [0x1f, 0x8b, 8, 4]
+        && bytes[12] == b'B'
+        && bytes[13] == b'C'
+}
+
+/// Read one compressed block, `None` at the end of the stream
+fn read_raw_block<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    let mut header = [0u8; HEADER_SIZE];
+    let mut filled = 0;
+    while filled < HEADER_SIZE {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated BGZF block header")),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    if header[..4] != [0x1f, 0x8b, 8, 4] {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a BGZF block"));
+    }
+
+    let xlen = u16::from_le_bytes([header[10], header[11]]) as usize;
+    let mut extra = vec![0u8; xlen];
+    reader.read_exact(&mut extra)?;
+
+    // Find the BC subfield holding the total block size - 1
+    let mut block_size = None;
+    let mut pos = 0;
+    while pos + 4 <= xlen {
+        let length = u16::from_le_bytes([extra[pos + 2], extra[pos + 3]]) as usize;
+        if extra[pos] == b'B' && extra[pos + 1] == b'C' && length == 2 && pos + 6 <= xlen {
+            block_size = Some(u16::from_le_bytes([extra[pos + 4], extra[pos + 5]]) as usize + 1);
+        }
+        pos += 4 + length;
+    }
+
+    let block_size = block_size.ok_or_else(|| Error::new(ErrorKind::InvalidData, "BGZF block without BC field"))?;
+    if block_size < HEADER_SIZE + xlen + FOOTER_SIZE {
+        return Err(Error::new(ErrorKind::InvalidData, "Invalid BGZF block size"));
+    }
+
+    let mut block = Vec::with_capacity(block_size);
+    block.extend_from_slice(&header);
+    block.extend_from_slice(&extra);
+    block.resize(block_size, 0);
+    reader.read_exact(&mut block[HEADER_SIZE + xlen..])?;
+
+    Ok(Some(block))
+}
+
+/// Decompress a complete BGZF block and verify its checksum
+pub fn inflate_block(block: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+    let xlen = u16::from_le_bytes([block[10], block[11]]) as usize;
+    let footer = &block[block.len() - FOOTER_SIZE..];
+    let crc = u32::from_le_bytes([footer[0], footer[1], footer[2], footer[3]]);
+    let size = u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]) as usize;
+
+    out.clear();
+    out.reserve(size);
+    let mut inflater = Decompress::new(false);
+    let status = inflater
+        .decompress_vec(&block[HEADER_SIZE + xlen..block.len() - FOOTER_SIZE], out, FlushDecompress::Finish)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+
+    if status != Status::StreamEnd || out.len() != size {
+        return Err(Error::new(ErrorKind::InvalidData, "BGZF block size does not match its content"));
+    }
+
+    let mut checksum = Crc::new();
+    checksum.update(out);
+    if checksum.sum() != crc {
+        return Err(Error::new(ErrorKind::InvalidData, "BGZF block checksum mismatch"));
+    }
+    Ok(())
+}
+
+type Job = (u64, Vec<u8>);
+type Inflated = (u64, Result<Vec<u8>, Error>);
+
+/// Threads decompressing blocks, results are tagged with the index of their block
+struct InflatePool {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<Inflated>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl InflatePool {
+    fn new(num_threads: usize) -> Self {
+        let (job_tx, job_rx) = mpsc::channel::<Job>();
+        let (result_tx, result_rx) = mpsc::channel::<Inflated>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+
+        let workers = (0..num_threads)
+            .map(|_| {
+                let job_rx = job_rx.clone();
+                let result_tx = result_tx.clone();
+                std::thread::spawn(move || loop {
+                    let job = job_rx.lock().expect("Locking job queue was unsuccessful").recv();
+                    let Ok((index, block)) = job else { break };
+
+                    let mut out = Vec::new();
+                    let result = inflate_block(&block, &mut out).map(|_| out);
+                    if result_tx.send((index, result)).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            jobs: Some(job_tx),
+            results: result_rx,
+            workers,
+        }
+    }
+}
+
+impl Drop for InflatePool {
+    fn drop(&mut self) {
+        // Closing the job queue stops the workers once they finished their current block
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Reader for BGZF compressed data (BAM files, bgzip compressed FASTQ/FASTA).
+///
+/// BGZF blocks are independent gzip members, so with more than one thread
+/// blocks are read ahead and decompressed in parallel while the output is
+/// still returned in file order.
+pub struct BgzfReader<R: Read> {
+    reader: R,
+    block: Vec<u8>,
+    block_pos: usize,
+    eof: bool,
+    pool: Option<InflatePool>,
+    next_submit: u64,
+    next_emit: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+    max_in_flight: usize,
+}
+
+impl<R: Read> BgzfReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_threads(reader, 1)
+    }
+
+    /// Decompress blocks on `num_threads` background threads, 1 decompresses on the calling thread
+    pub fn with_threads(reader: R, num_threads: usize) -> Self {
+        let pool = (num_threads > 1).then(|| InflatePool::new(num_threads));
+        Self {
+            reader,
+            block: Vec::new(),
+            block_pos: 0,
+            eof: false,
+            pool,
+            next_submit: 0,
+            next_emit: 0,
+            pending: BTreeMap::new(),
+            max_in_flight: num_threads * BLOCKS_PER_THREAD,
+        }
+    }
+
+    /// Load the next decompressed block, false at the end of the stream
+    fn next_block(&mut self) -> Result<bool, Error> {
+        let Some(pool) = &self.pool else {
+            return match read_raw_block(&mut self.reader)? {
+                Some(raw) => {
+                    inflate_block(&raw, &mut self.block)?;
+                    self.block_pos = 0;
+                    Ok(true)
+                }
+                None => Ok(false),
+            };
+        };
+
+        let jobs = pool.jobs.as_ref().expect("Job queue is open while reading");
+        while !self.eof && ((self.next_submit - self.next_emit) as usize) < self.max_in_flight {
+            match read_raw_block(&mut self.reader)? {
+                Some(raw) => {
+                    jobs.send((self.next_submit, raw))
+                        .map_err(|_| Error::other("BGZF decompression thread stopped"))?;
+                    self.next_submit += 1;
+                }
+                None => self.eof = true,
+            }
+        }
+
+        if self.next_emit == self.next_submit {
+            return Ok(false);
+        }
+
+        let block = loop {
+            if let Some(block) = self.pending.remove(&self.next_emit) {
+                break block;
+            }
+            let (index, result) = pool
+                .results
+                .recv()
+                .map_err(|_| Error::other("BGZF decompression thread stopped"))?;
+            self.pending.insert(index, result?);
+        };
+
+        self.next_emit += 1;
+        self.block = block;
+        self.block_pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Loop as empty blocks (e.g. the EOF marker) are valid
+        while self.block_pos >= self.block.len() {
+            if !self.next_block()? {
+                return Ok(0);
+            }
+        }
+
+        let n = std::cmp::min(buf.len(), self.block.len() - self.block_pos);
+        buf[..n].copy_from_slice(&self.block[self.block_pos..self.block_pos + n]);
+        self.block_pos += n;
+        Ok(n)
+    }
+}
+
+/// Compress data into a single BGZF block, `data` must not exceed 64 KiB
+pub fn deflate_block(data: &[u8], level: flate2::Compression, block: &mut Vec<u8>) -> Result<(), Error> {
+    block.clear();
+    block.extend_from_slice(&BLOCK_HEADER);
+    block.extend_from_slice(&[0, 0]);
+    let mut encoder = DeflateEncoder::new(std::mem::take(block), level);
+    encoder.write_all(data)?;
+    *block = encoder.finish()?;
+
+    let mut crc = Crc::new();
+    crc.update(data);
+    block.extend_from_slice(&crc.sum().to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+    if block.len() > MAX_BLOCK_SIZE {
+        return Err(Error::new(ErrorKind::InvalidInput, "Data does not fit into a BGZF block"));
+    }
+    let block_size = (block.len() - 1) as u16;
+    block[BLOCK_HEADER.len()..BLOCK_HEADER.len() + 2].copy_from_slice(&block_size.to_le_bytes());
+    Ok(())
+}
+
+/// Writer for BGZF compressed data.
+///
+/// Data is compressed in independent blocks of at most 64 KiB, [`BgzfWriter::finish`]
+/// appends the empty block that marks the end of the file.
+pub struct BgzfWriter<W: Write> {
+    writer: Option<W>,
+    data: Vec<u8>,
+    block: Vec<u8>,
+    level: flate2::Compression,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_level(writer, flate2::Compression::default())
+    }
+
+    pub fn with_level(writer: W, level: flate2::Compression) -> Self {
+        Self {
+            writer: Some(writer),
+            data: Vec::with_capacity(MAX_BLOCK_DATA),
+            block: Vec::with_capacity(MAX_BLOCK_SIZE),
+            level,
+        }
+    }
+
+    /// Compress and write the buffered data as one block
+    fn write_block(&mut self) -> Result<(), Error> {
+        deflate_block(&self.data, self.level, &mut self.block)?;
+        self.writer.as_mut().expect("Writer is present until finished").write_all(&self.block)?;
+        self.data.clear();
+        Ok(())
+    }
+
+    /// Write the buffered data and the EOF marker block
+    fn try_finish(&mut self) -> Result<(), Error> {
+        if !self.data.is_empty() {
+            self.write_block()?;
+        }
+        self.write_block()?;
+        self.writer.as_mut().expect("Writer is present until finished").flush()
+    }
+
+    /// Write the remaining data and the EOF marker, and return the underlying writer
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.try_finish()?;
+        Ok(self.writer.take().expect("Writer is present until finished"))
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.data.len() == MAX_BLOCK_DATA {
+            self.write_block()?;
+        }
+        let n = std::cmp::min(buf.len(), MAX_BLOCK_DATA - self.data.len());
+        self.data.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    /// Writes the buffered data as a (possibly short) block, which keeps the output valid BGZF
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.data.is_empty() {
+            self.write_block()?;
+        }
+        self.writer.as_mut().expect("Writer is present until finished").flush()
+    }
+}
+
+impl<W: Write> Drop for BgzfWriter<W> {
+    fn drop(&mut self) {
+        // Like the gzip encoder, finish the stream if the writer was not finished explicitly
+        if self.writer.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Compress data into a single BGZF block
+    pub(crate) fn bgzf_block(data: &[u8]) -> Vec<u8> {
+        let mut block = Vec::new();
+        deflate_block(data, flate2::Compression::default(), &mut block).unwrap();
+        block
+    }
+
+    /// Compress data in blocks of at most 1000 bytes followed by the EOF marker
+    pub(crate) fn bgzf(data: &[u8]) -> Vec<u8> {
+        let mut compressed: Vec<u8> = data.chunks(1000).flat_map(bgzf_block).collect();
+        compressed.extend(bgzf_block(b""));
+        compressed
+    }
+
+    #[test]
+    fn test_bgzf_reader() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let compressed = bgzf(&data);
+        assert!(is_bgzf(&compressed));
+
+        for threads in [1, 4] {
+            let mut out = Vec::new();
+            BgzfReader::with_threads(&compressed[..], threads).read_to_end(&mut out).unwrap();
+            assert_eq!(out, data);
+        }
+    }
+
+    #[test]
+    fn test_bgzf_writer() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        let mut writer = BgzfWriter::new(Vec::new());
+        writer.write_all(&data).unwrap();
+        let compressed = writer.finish().unwrap();
+        assert!(is_bgzf(&compressed));
+        // Ends with the EOF marker block
+        assert!(compressed.ends_with(&bgzf_block(b"")));
+
+        let mut out = Vec::new();
+        BgzfReader::new(&compressed[..]).read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+
+        // Every block is a gzip member of at most 64 KiB
+        let mut reader = &compressed[..];
+        let mut blocks = 0;
+        while let Some(block) = read_raw_block(&mut reader).unwrap() {
+            assert!(block.len() <= MAX_BLOCK_SIZE);
+            blocks += 1;
+        }
+        assert_eq!(blocks, data.len().div_ceil(MAX_BLOCK_DATA) + 1);
+    }
+
+    #[test]
+    fn test_corrupt_block() {
+        let mut compressed = bgzf(b"ACGTACGTACGT");
+        let crc_pos = compressed.len() - 28 - FOOTER_SIZE;
+        compressed[crc_pos] ^= 0xff;
+
+        let mut out = Vec::new();
+        assert!(BgzfReader::new(&compressed[..]).read_to_end(&mut out).is_err());
+        assert!(BgzfReader::with_threads(&compressed[..], 2).read_to_end(&mut out).is_err());
+    }
+}
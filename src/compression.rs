@@ -0,0 +1,218 @@
+use std::{
+    io::{Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+
+use crate::{
+    bgzf::{is_bgzf, BgzfReader, BgzfWriter},
+    read_ahead::ReadAhead,
+};
+
+/// Number of bytes needed by [`Compression::detect`]
+pub const MAGIC_LEN: usize = 18;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+/// Reader returned by [`Compression::decoder`]
+pub type DynRead<'a> = Box<dyn Read + Send + 'a>;
+
+/// Compression of an input or output file
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    /// Blocked gzip as used by BAM files and bgzip
+    Bgzf,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// Guess the compression from the file extension of an output path
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Self::Gzip,
+            Some("bgz") => Self::Bgzf,
+            Some("zst") => Self::Zstd,
+            Some("bz2") => Self::Bzip2,
+            Some("xz") => Self::Xz,
+            _ => Self::None,
+        }
+    }
+
+    /// Detect the compression from the magic bytes at the start of a file
+    pub fn detect(bytes: &[u8]) -> Self {
+        if is_bgzf(bytes) {
+            Self::Bgzf
+        } else if bytes.starts_with(GZIP_MAGIC) {
+            Self::Gzip
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Self::Zstd
+        } else if bytes.starts_with(BZIP2_MAGIC) {
+            Self::Bzip2
+        } else if bytes.starts_with(XZ_MAGIC) {
+            Self::Xz
+        } else {
+            Self::None
+        }
+    }
+
+    /// Wrap a reader so that it returns decompressed data
+    pub fn decoder<'a, R: Read + Send + 'a>(self, reader: R) -> Result<DynRead<'a>, Error> {
+        match self {
+            Self::None => Ok(Box::new(reader)),
+            // Concatenated gzip members are common in sequencing data
+            Self::Gzip => Ok(Box::new(MultiGzDecoder::new(reader))),
+            Self::Bgzf => Ok(Box::new(BgzfReader::new(reader))),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(Box::new(zstd::Decoder::new(reader)?)),
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2 => Ok(Box::new(bzip2::read::MultiBzDecoder::new(reader))),
+            #[cfg(feature = "xz")]
+            Self::Xz => Ok(Box::new(xz2::read::XzDecoder::new_multi_decoder(reader))),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    /// Like [`Compression::decoder`] but decompress off the calling thread.
+    ///
+    /// BGZF blocks are inflated on a pool of `num_threads` threads, other
+    /// compressed streams are decompressed on a single thread ahead of the reader.
+    pub fn decoder_par<R: Read + Send + 'static>(self, reader: R, num_threads: usize) -> Result<DynRead<'static>, Error> {
+        match self {
+            Self::None => Ok(Box::new(reader)),
+            _ if num_threads <= 1 => self.decoder(reader),
+            Self::Bgzf => Ok(Box::new(BgzfReader::with_threads(reader, num_threads))),
+            _ => Ok(Box::new(ReadAhead::new(self.decoder(reader)?))),
+        }
+    }
+
+    pub fn encoder<W: Write>(self, writer: W) -> Result<Encoder<W>, Error> {
+        let inner = match self {
+            Self::None => EncoderInner::Plain(writer),
+            Self::Gzip => EncoderInner::Gzip(GzEncoder::new(writer, flate2::Compression::default())),
+            Self::Bgzf => EncoderInner::Bgzf(BgzfWriter::new(writer)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => EncoderInner::Zstd(zstd::Encoder::new(writer, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+            _ => return Err(self.unsupported()),
+        };
+        Ok(Encoder { inner })
+    }
+
+    /// Error for compressions that are not compiled in or, like bzip2 and xz, only supported for reading
+    fn unsupported(self) -> Error {
+        let message = match self {
+            Self::Bzip2 if cfg!(feature = "bzip2") => "bzip2 compression is only supported for reading".to_string(),
+            Self::Xz if cfg!(feature = "xz") => "xz compression is only supported for reading".to_string(),
+            Self::Zstd => "zstd compression requires the `zstd` feature".to_string(),
+            Self::Bzip2 => "bzip2 compression requires the `bzip2` feature".to_string(),
+            Self::Xz => "xz compression requires the `xz` feature".to_string(),
+            _ => format!("{:?} compression is not supported by this build", self),
+        };
+        Error::new(ErrorKind::Unsupported, message)
+    }
+}
+
+/// Sniff the compression of a reader and return a reader of its decompressed content
+pub fn decode<'a, R: Read + Send + 'a>(mut reader: R) -> Result<(Compression, DynRead<'a>), Error> {
+    let magic = peek(&mut reader, MAGIC_LEN)?;
+    let compression = Compression::detect(&magic);
+    let reader = compression.decoder(std::io::Cursor::new(magic).chain(reader))?;
+    Ok((compression, reader))
+}
+
+/// Like [`decode`] but decompress on `num_threads` threads, see [`Compression::decoder_par`]
+pub fn decode_par<R: Read + Send + 'static>(mut reader: R, num_threads: usize) -> Result<(Compression, DynRead<'static>), Error> {
+    let magic = peek(&mut reader, MAGIC_LEN)?;
+    let compression = Compression::detect(&magic);
+    let reader = compression.decoder_par(std::io::Cursor::new(magic).chain(reader), num_threads)?;
+    Ok((compression, reader))
+}
+
+/// Open a file and decompress it according to its magic bytes
+pub fn open(path: impl AsRef<Path>) -> Result<DynRead<'static>, Error> {
+    decode(std::fs::File::open(path)?).map(|(_, reader)| reader)
+}
+
+/// Open a file and decompress it on `num_threads` threads
+pub fn open_par(path: impl AsRef<Path>, num_threads: usize) -> Result<DynRead<'static>, Error> {
+    decode_par(std::fs::File::open(path)?, num_threads).map(|(_, reader)| reader)
+}
+
+/// Read up to `n` bytes, they need to be chained in front of the reader again
+pub(crate) fn peek<R: Read>(reader: &mut R, n: usize) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::with_capacity(n);
+    reader.by_ref().take(n as u64).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+enum EncoderInner<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Bgzf(BgzfWriter<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+/// Writer that compresses everything written to it according to a [`Compression`]
+pub struct Encoder<W: Write> {
+    inner: EncoderInner<W>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Write any trailing compression data and return the underlying writer
+    pub fn finish(self) -> Result<W, Error> {
+        match self.inner {
+            EncoderInner::Plain(mut writer) => {
+                writer.flush()?;
+                Ok(writer)
+            }
+            EncoderInner::Gzip(encoder) => encoder.finish(),
+            EncoderInner::Bgzf(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            EncoderInner::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.inner {
+            EncoderInner::Plain(writer) => writer.write(buf),
+            EncoderInner::Gzip(encoder) => encoder.write(buf),
+            EncoderInner::Bgzf(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            EncoderInner::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match &mut self.inner {
+            EncoderInner::Plain(writer) => writer.write_all(buf),
+            EncoderInner::Gzip(encoder) => encoder.write_all(buf),
+            EncoderInner::Bgzf(encoder) => encoder.write_all(buf),
+            #[cfg(feature = "zstd")]
+            EncoderInner::Zstd(encoder) => encoder.write_all(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.inner {
+            EncoderInner::Plain(writer) => writer.flush(),
+            EncoderInner::Gzip(encoder) => encoder.flush(),
+            EncoderInner::Bgzf(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            EncoderInner::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
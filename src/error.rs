@@ -0,0 +1,180 @@
+use std::{
+    fmt::{self, Display},
+    io::ErrorKind,
+};
+
+use crate::compression::Compression;
+
+/// Location of a record in its (decompressed) input
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RecordPosition {
+    /// Offset of the first byte of the record
+    pub byte_offset: u64,
+    /// Number of the record in the input, starting at 1
+    pub record_number: u64,
+}
+
+impl Default for RecordPosition {
+    fn default() -> Self {
+        Self {
+            byte_offset: 0,
+            record_number: 1,
+        }
+    }
+}
+
+impl RecordPosition {
+    /// Position of the `index`th record of a chunk starting at `self`, which starts at byte `start` of the chunk
+    #[inline]
+    pub fn in_chunk(&self, start: usize, index: u64) -> Self {
+        Self {
+            byte_offset: self.byte_offset + start as u64,
+            record_number: self.record_number + index,
+        }
+    }
+
+    /// Advance past a chunk of `bytes` bytes holding `records` records
+    #[inline]
+    pub fn advance(&mut self, bytes: usize, records: usize) {
+        self.byte_offset += bytes as u64;
+        self.record_number += records as u64;
+    }
+}
+
+impl Display for RecordPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "record {} (byte offset {})", self.record_number, self.byte_offset)
+    }
+}
+
+/// Errors of all sequence readers
+#[derive(Debug)]
+pub enum BioReaderError {
+    Io(std::io::Error),
+    /// The input ends within a record
+    TruncatedRecord { position: RecordPosition },
+    /// The third line of a FASTQ record does not start with `+`
+    MissingSeparator { position: RecordPosition },
+    /// Sequence and quality of a FASTQ record differ in length
+    LengthMismatch {
+        sequence: usize,
+        quality: usize,
+        position: RecordPosition,
+    },
+    /// Unexpected byte, e.g. a record not starting with `@`/`>` or a base outside the alphabet
+    InvalidByte { byte: u8, position: RecordPosition },
+    /// One file of a pair has more records than the other
+    UnpairedReads { position: RecordPosition },
+    /// A record does not fit within the maximum buffer size of a byte reader
+    RecordTooLarge {
+        max_buffer_size: usize,
+        position: RecordPosition,
+    },
+    /// A SAM or BAM header line breaks the format, `position` counts the header lines as records
+    InvalidHeader { reason: String, position: RecordPosition },
+    /// A SAM or BAM record breaks the format
+    InvalidRecord { reason: String, position: RecordPosition },
+    /// The input is neither FASTQ, FASTA, SAM nor BAM
+    UnknownFormat,
+    /// A BAM file that is not BGZF compressed, e.g. plain gzip
+    BamNotBgzf { compression: Compression },
+    /// A worker closure returned an error for the record at `position` in chunk `chunk`
+    WorkerFailed {
+        chunk: u64,
+        position: RecordPosition,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// A worker closure panicked in chunk `chunk`, `position` is the last record handed to it
+    WorkerPanicked {
+        chunk: u64,
+        position: RecordPosition,
+        message: String,
+    },
+}
+
+impl BioReaderError {
+    /// Position of the offending record, `None` for IO and format detection errors
+    pub fn position(&self) -> Option<RecordPosition> {
+        match self {
+            Self::Io(_) | Self::UnknownFormat | Self::BamNotBgzf { .. } => None,
+            Self::TruncatedRecord { position }
+            | Self::MissingSeparator { position }
+            | Self::LengthMismatch { position, .. }
+            | Self::InvalidByte { position, .. }
+            | Self::UnpairedReads { position }
+            | Self::RecordTooLarge { position, .. }
+            | Self::InvalidHeader { position, .. }
+            | Self::InvalidRecord { position, .. }
+            | Self::WorkerFailed { position, .. }
+            | Self::WorkerPanicked { position, .. } => Some(*position),
+        }
+    }
+}
+
+impl Display for BioReaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "IO error: {}", err),
+            Self::TruncatedRecord { position } => write!(f, "Truncated record at {}", position),
+            Self::MissingSeparator { position } => write!(f, "Missing '+' separator line at {}", position),
+            Self::LengthMismatch {
+                sequence,
+                quality,
+                position,
+            } => write!(
+                f,
+                "Sequence length {} does not match quality length {} at {}",
+                sequence, quality, position
+            ),
+            Self::InvalidByte { byte, position } => {
+                write!(f, "Invalid byte '{}' at {}", byte.escape_ascii(), position)
+            }
+            Self::UnpairedReads { position } => write!(f, "Paired files differ in length at {}", position),
+            Self::RecordTooLarge {
+                max_buffer_size,
+                position,
+            } => write!(
+                f,
+                "Record at {} does not fit within the maximum buffer size of {} bytes",
+                position, max_buffer_size
+            ),
+            Self::InvalidHeader { reason, position } => write!(f, "Invalid header line at {}: {}", position, reason),
+            Self::InvalidRecord { reason, position } => write!(f, "Invalid record at {}: {}", position, reason),
+            Self::UnknownFormat => write!(f, "Unable to detect the input format"),
+            Self::BamNotBgzf { compression } => {
+                write!(f, "BAM file is not BGZF compressed but uses {:?} compression", compression)
+            }
+            Self::WorkerFailed { chunk, position, source } => {
+                write!(f, "Worker failed in chunk {} at {}: {}", chunk, position, source)
+            }
+            Self::WorkerPanicked { chunk, position, message } => {
+                write!(f, "Worker panicked in chunk {} at {}: {}", chunk, position, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BioReaderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::WorkerFailed { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BioReaderError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<BioReaderError> for std::io::Error {
+    fn from(err: BioReaderError) -> Self {
+        match err {
+            BioReaderError::Io(err) => err,
+            err => std::io::Error::new(ErrorKind::InvalidData, err),
+        }
+    }
+}
@@ -8,9 +8,17 @@
 //     fn fill_buf(&mut self, buffer: &mut Vec<u8>) -> Result<FastaLoadBatch, std::io::Error>;
 // }
 
-use crate::fastq_byte_reader::FillBuffer;
+use crate::{error::{BioReaderError, RecordPosition}, fastq_byte_reader::{grow_buffer, FillBuffer}};
 
 
+/// Number of headers in a chunk of complete FASTA records
+#[inline]
+fn count_fasta_records(chunk: &[u8]) -> usize {
+    memchr::memchr_iter(b'>', chunk)
+        .filter(|&i| i == 0 || chunk[i - 1] == b'\n')
+        .count()
+}
+
 pub struct FastaByteReader<T>
 where
     T: std::io::Read,
@@ -19,10 +27,21 @@ where
     buffer_fill: usize,
     buffer: Vec<u8>,
     finished: bool,
+    max_buffer_size: Option<usize>,
+    chunk_position: RecordPosition,
+    chunk_index: u64,
 }
 
 impl<T: std::io::Read> FillBuffer for FastaByteReader<T> {
-    fn fill_buf(&mut self, buf: &mut Vec<u8>) -> Result<Option<usize>, std::io::Error> {
+    fn position(&self) -> RecordPosition {
+        self.chunk_position
+    }
+
+    fn chunk_index(&self) -> u64 {
+        self.chunk_index
+    }
+
+    fn fill_buf(&mut self, buf: &mut Vec<u8>) -> Result<Option<usize>, BioReaderError> {
         // Implies that file has been read to the end, but there still might be data in the buffer
         if self.finished {
             if self.buffer_fill == 0 {
@@ -31,6 +50,8 @@ impl<T: std::io::Read> FillBuffer for FastaByteReader<T> {
                 buf[..self.buffer_fill].copy_from_slice(&self.buffer[..self.buffer_fill]);
                 let fill = self.buffer_fill;
                 self.buffer_fill = 0;
+                self.chunk_position.advance(fill, count_fasta_records(&buf[..fill]));
+                self.chunk_index += 1;
                 return Ok(Some(fill));
             } // File has been read and buffer is empty
         }
@@ -43,19 +64,28 @@ impl<T: std::io::Read> FillBuffer for FastaByteReader<T> {
         let mut index = self.find_next(&self.buffer[..self.buffer_fill]);
 
         while index == 0 && !self.finished {
-            self.buffer.resize(self.buffer.len() * 2, 0);
+            grow_buffer(&mut self.buffer, self.max_buffer_size, self.chunk_position)?;
             self.read_file()?;
             index = self.find_next(&self.buffer[..self.buffer_fill]);
         }
+        // The last record of the file is not followed by a header
+        if index == 0 {
+            index = self.buffer_fill;
+        }
+        if index == 0 {
+            return Ok(None);
+        }
         buf.resize(self.buffer.len(), 0);
 
 
         let buffer_slice = &self.buffer[..self.buffer_fill];
 
-        assert!(index < buf.len());
+        assert!(index <= buf.len());
 
         // Copy local buffer of complete Fastq records into external buffer
         buf[..index].copy_from_slice(&buffer_slice[..index]);
+        self.chunk_position.advance(index, count_fasta_records(&buf[..index]));
+        self.chunk_index += 1;
         // println!("- Bytes: {index} {}", buf.len());
         // println!("- End: {}", std::str::from_utf8(&buf[index-200..index]).unwrap());
 
@@ -68,7 +98,7 @@ impl<T: std::io::Read> FillBuffer for FastaByteReader<T> {
             self.read_file()?;
         }
         
-        return Ok(Some(index))
+        Ok(Some(index))
     }
 }
 
@@ -79,12 +109,22 @@ impl<T: std::io::Read> FastaByteReader<T> {
             buffer_fill: 0,
             buffer: vec![0; chunk_size],
             finished: false,
+            max_buffer_size: None,
+            chunk_position: RecordPosition::default(),
+            chunk_index: 0,
         };
         br.read_file()?;
 
         Ok(br)
     }
 
+    /// Limit the growth of the buffer for records larger than the chunk size,
+    /// larger records fail with [`BioReaderError::RecordTooLarge`]
+    pub fn with_max_buffer_size(mut self, max_buffer_size: Option<usize>) -> Self {
+        self.max_buffer_size = max_buffer_size;
+        self
+    }
+
     pub fn find_next(&self, buffer_slice: &[u8]) -> usize {
         for (i, &c) in buffer_slice.iter().enumerate().rev() {
             if i > 1 && c == b'>' && buffer_slice[i - 1] == b'\n' {
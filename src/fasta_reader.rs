@@ -1,6 +1,7 @@
-use std::sync::{Arc, Mutex};
-use crate::{fastq_byte_reader::FillBuffer, sequence::fasta_record::OwnedFastaRecord};
-use memchr::memchr;
+use lending_iterator::prelude::*;
+use std::{path::Path, sync::{Arc, Mutex}};
+use crate::{compression::{open, DynRead}, error::{BioReaderError, RecordPosition}, fasta_byte_reader::FastaByteReader, fastq_byte_reader::{Chunk, FillBuffer}, format::DEFAULT_CHUNK_SIZE, sequence::fasta_record::{OwnedFastaRecord, RefFastaRecord}};
+use memchr::{memchr, memmem};
 
 
 #[derive(Debug, Clone)]
@@ -8,6 +9,10 @@ pub struct FastaReader {
     pub buffer: Vec<u8>,
     pub buffer_pos: usize,
     pub buffer_fill: usize,
+    chunk_position: RecordPosition,
+    chunk_index: u64,
+    record_start: usize,
+    record_index: u64,
 }
 
 impl FastaReader {
@@ -16,6 +21,10 @@ impl FastaReader {
             buffer: vec![0; capacity],
             buffer_pos: 0,
             buffer_fill: 0,
+            chunk_position: RecordPosition::default(),
+            chunk_index: 0,
+            record_start: 0,
+            record_index: 0,
         }
     }
 
@@ -24,13 +33,14 @@ impl FastaReader {
     }
 
     #[inline]
-    pub fn load_batch_par(&mut self, br: &mut Arc<Mutex<impl FillBuffer>>) -> Result<Option<()>, std::io::Error> {
+    pub fn load_batch(&mut self, br: &mut impl FillBuffer) -> Result<Option<()>, BioReaderError> {
         self.buffer_pos = 0;
+        self.record_start = 0;
+        self.record_index = 0;
 
-        let bytes = match br.lock().expect("Locking ByteReader was unsuccessful").fill_buf(&mut self.buffer)? {
-            Some(bytes) => bytes,
-            None => 0,
-        };
+        self.chunk_position = br.position();
+        self.chunk_index = br.chunk_index();
+        let bytes = br.fill_buf(&mut self.buffer)?.unwrap_or_default();
 
         self.buffer_fill = bytes;
 
@@ -39,42 +49,207 @@ impl FastaReader {
         }
 
         Ok(Some(()))
-     }
+    }
+
+    #[inline]
+    pub fn load_batch_par(&mut self, br: &mut Arc<Mutex<impl FillBuffer>>) -> Result<Option<()>, BioReaderError> {
+        let mut br = br.lock().expect("Locking ByteReader was unsuccessful");
+        self.load_batch(&mut *br)
+    }
+
+    /// Take over a chunk filled by another thread, returns the previous buffer for reuse
+    pub fn load_chunk(&mut self, chunk: Chunk) -> Vec<u8> {
+        self.buffer_pos = 0;
+        self.record_start = 0;
+        self.record_index = 0;
+        self.chunk_position = chunk.position;
+        self.chunk_index = chunk.index;
+        self.buffer_fill = chunk.fill;
+        std::mem::replace(&mut self.buffer, chunk.buffer)
+    }
+
+    /// Position of the record last read by [`FastaReader::next`]
+    pub fn position(&self) -> RecordPosition {
+        self.chunk_position.in_chunk(self.record_start, self.record_index.saturating_sub(1))
+    }
+
+    /// Sequence number of the loaded chunk in its input
+    pub fn chunk_index(&self) -> u64 {
+        self.chunk_index
+    }
+
+    /// Copy the next record into `record`, see [`FastaReader::next_ref`] to borrow it instead
+    pub fn next(&mut self, record: &mut OwnedFastaRecord) -> Result<Option<()>, BioReaderError> {
+        let Some(next) = self.next_ref()? else {
+            return Ok(None);
+        };
+        next.copy_into(record);
+        Ok(Some(()))
+    }
 
-    pub fn next(&mut self, record: &mut OwnedFastaRecord) -> Option<()> {
+    /// Next record of the chunk, borrowed from the buffer
+    pub fn next_ref(&mut self) -> Result<Option<RefFastaRecord<'_>>, BioReaderError> {
         if self.buffer_pos >= self.buffer_fill {
-            return None
+            return Ok(None)
         }
 
-        assert!(self.buffer[self.buffer_pos] == b'>');
+        let buffer = &self.buffer[..self.buffer_fill];
+        let position = self.chunk_position.in_chunk(self.buffer_pos, self.record_index);
+        if buffer[self.buffer_pos] != b'>' {
+            return Err(BioReaderError::InvalidByte { byte: buffer[self.buffer_pos], position });
+        }
         let header_start: usize = self.buffer_pos;
-        let header_length: usize = memchr(b'\n', &self.buffer[self.buffer_pos..]).expect("1: Couldn't find newline");
-        self.buffer_pos += header_length + 1;
-
-        let mut length = 0;
+        let header_length: usize = memchr(b'\n', &buffer[header_start..])
+            .ok_or(BioReaderError::TruncatedRecord { position })?;
+        let seq_start = header_start + header_length + 1;
+        self.record_start = header_start;
+        self.record_index += 1;
+
+        // The sequence ends before the next line starting with '>'
+        let seq_end = match buffer.get(seq_start) {
+            Some(b'>') => seq_start,
+            _ => memmem::find(&buffer[seq_start..], b"\n>").map_or(buffer.len(), |pos| seq_start + pos + 1),
+        };
+        self.buffer_pos = seq_end;
 
-        record.clear();
-        record.header.extend_from_slice(&self.buffer[header_start..header_start + header_length]);
+        Ok(Some(RefFastaRecord::new(
+            &buffer[header_start..header_start + header_length],
+            &buffer[seq_start..seq_end],
+        )))
+    }
+}
+impl FastaReader {
+    /// Records of a FASTA file with any supported compression
+    pub fn from_path(path: impl AsRef<Path>) -> Result<FastaRecords<FastaByteReader<DynRead<'static>>>, BioReaderError> {
+        let byte_reader = FastaByteReader::new(open(path)?, DEFAULT_CHUNK_SIZE)?;
+        Ok(FastaReader::with_capacity(DEFAULT_CHUNK_SIZE).records(byte_reader))
+    }
 
-        while self.buffer_pos < self.buffer_fill - 1 && self.buffer[self.buffer_pos] != b'>' {
-            let newline_pos = match memchr(b'\n', &self.buffer[self.buffer_pos..]) {
-                Some(pos) => pos,
-                None => {
-                    self.buffer_fill - self.buffer_pos
-                },
-            };
+    /// Records of `byte_reader`, loading its chunks into this reader as needed
+    pub fn records<B: FillBuffer>(self, byte_reader: B) -> FastaRecords<B> {
+        FastaRecords { byte_reader, reader: self, failed: false }
+    }
+}
 
-            length = newline_pos;
+/// [`LendingIterator`] over the records of a byte reader, see [`FastaRecords::owned`] for an [`Iterator`]
+pub struct FastaRecords<B> {
+    byte_reader: B,
+    reader: FastaReader,
+    // Errors do not advance the reader, it would return the same error again
+    failed: bool,
+}
 
-            if length > record.sequence.len() {
-                record.sequence.reserve(length);
+impl<B: FillBuffer> FastaRecords<B> {
+    /// Next record, `None` after the first error
+    pub fn next_record(&mut self) -> Result<Option<RefFastaRecord<'_>>, BioReaderError> {
+        if self.failed {
+            return Ok(None);
+        }
+        while self.reader.buffer_pos >= self.reader.buffer_fill {
+            match self.reader.load_batch(&mut self.byte_reader) {
+                Ok(Some(())) => {}
+                Ok(None) => return Ok(None),
+                Err(err) => {
+                    self.failed = true;
+                    return Err(err);
+                }
             }
+        }
+        let record = self.reader.next_ref();
+        self.failed = record.is_err();
+        record
+    }
+
+    /// Position of the record last returned
+    pub fn position(&self) -> RecordPosition {
+        self.reader.position()
+    }
+
+    /// Iterator over copies of the records
+    pub fn owned(self) -> OwnedFastaRecords<B> {
+        OwnedFastaRecords(self)
+    }
+}
+
+#[gat]
+impl<B: FillBuffer> LendingIterator for FastaRecords<B> {
+    type Item<'next>
+    where
+        Self: 'next,
+    = Result<RefFastaRecord<'next>, BioReaderError>;
 
-            record.sequence.extend_from_slice(&self.buffer[self.buffer_pos..self.buffer_pos+newline_pos]);
+    fn next(&mut self) -> Option<Item<'_, Self>> {
+        self.next_record().transpose()
+    }
+}
+
+pub struct OwnedFastaRecords<B>(FastaRecords<B>);
+
+impl<B: FillBuffer> Iterator for OwnedFastaRecords<B> {
+    type Item = Result<OwnedFastaRecord, BioReaderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_record().transpose().map(|record| record.map(|record| record.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ref_records() {
+        let fasta = b">chr1 first\nACGT\nAC\n>chr2\n>chr3\nGG\n\nTT".to_vec();
+        let mut reader = FastaReader::with_capacity(0);
+        reader.load_chunk(Chunk { fill: fasta.len(), buffer: fasta, ..Default::default() });
+
+        let record = reader.next_ref().unwrap().unwrap();
+        assert_eq!(record.head(), b">chr1 first");
+        assert_eq!(record.seq_lines().collect::<Vec<_>>(), [b"ACGT".as_slice(), b"AC"]);
+        assert_eq!(record.seq_len(), 6);
+        assert_eq!(record.to_owned().seq(), b"ACGTAC");
+
+        let record = reader.next_ref().unwrap().unwrap();
+        assert_eq!((record.head(), record.seq_len()), (b">chr2".as_slice(), 0));
+
+        // Copied records match the borrowed ones
+        let mut owned = OwnedFastaRecord::new();
+        assert!(reader.next(&mut owned).unwrap().is_some());
+        assert_eq!((owned.head(), owned.seq()), (b">chr3".as_slice(), b"GGTT".as_slice()));
+        assert!(reader.next_ref().unwrap().is_none());
+    }
 
-            self.buffer_pos += newline_pos + 1;
+    #[test]
+    fn test_from_path() {
+        let fasta = (0..100).map(|i| format!(">chr{i}\nACGTACGT\nACGT\n")).collect::<String>();
+        let path = std::env::temp_dir().join(format!("bioreader_test_{}.fa", std::process::id()));
+        std::fs::write(&path, &fasta).unwrap();
+        let records = FastaReader::from_path(&path).unwrap().owned().collect::<Result<Vec<_>, _>>();
+        std::fs::remove_file(&path).unwrap();
+
+        let records = records.unwrap();
+        assert_eq!(records.len(), 100);
+        assert!(records.iter().all(|record| record.seq() == b"ACGTACGTACGT"));
+
+        // Small chunks need several sequential batches
+        let byte_reader = FastaByteReader::new(std::io::Cursor::new(fasta), 64).unwrap();
+        let mut records = FastaReader::with_capacity(64).records(byte_reader);
+        let mut count = 0;
+        while let Some(record) = records.next() {
+            assert_eq!(record.unwrap().head(), format!(">chr{count}").as_bytes());
+            count += 1;
         }
+        assert_eq!(count, 100);
+    }
 
-        Some(())
+    #[test]
+    fn test_records_stop_after_error() {
+        let fasta = b"ACGT\n>chr1\nACGT\n".to_vec();
+        let byte_reader = FastaByteReader::new(std::io::Cursor::new(fasta), 64).unwrap();
+        let mut records = FastaReader::with_capacity(64).records(byte_reader);
+        assert!(matches!(records.next(), Some(Err(BioReaderError::InvalidByte { byte: b'A', .. }))));
+        for _ in 0..10 {
+            assert!(records.next().is_none());
+        }
     }
-}
\ No newline at end of file
+}
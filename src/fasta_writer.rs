@@ -0,0 +1,162 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Error, Write},
+    path::Path,
+};
+
+use crate::{
+    compression::{Compression, Encoder},
+    sequence::fasta_record::OwnedFastaRecord,
+};
+
+const DEFAULT_CAPACITY: usize = 1 << 20;
+
+/// Default number of bases per sequence line
+pub const DEFAULT_LINE_WIDTH: usize = 60;
+
+/// Append a FASTA record to a byte buffer, wrapping the sequence after
+/// `line_width` bases or writing it on a single line if `None`. An empty
+/// sequence writes no sequence line at all, only the header.
+///
+/// The header is written as is if it already starts with '>', as headers
+/// produced by [`crate::fasta_reader::FastaReader`] do.
+#[inline]
+pub fn format_record(head: &[u8], seq: &[u8], line_width: Option<usize>, out: &mut Vec<u8>) {
+    if head.first() != Some(&b'>') {
+        out.push(b'>');
+    }
+    out.extend_from_slice(head);
+    out.push(b'\n');
+
+    match line_width {
+        Some(width) if width > 0 => {
+            out.reserve(seq.len() + seq.len() / width + 1);
+            for line in seq.chunks(width) {
+                out.extend_from_slice(line);
+                out.push(b'\n');
+            }
+        }
+        _ if !seq.is_empty() => {
+            out.extend_from_slice(seq);
+            out.push(b'\n');
+        }
+        _ => {}
+    }
+}
+
+/// Buffered FASTA writer with configurable line wrapping and optional compression.
+///
+/// To write from the worker closures of [`crate::parallel::fastq::read_fasta_par`],
+/// either share the writer behind an `Arc<Mutex<_>>` or format records into a
+/// thread local buffer with [`format_record`] and pass it to [`FastaWriter::write_chunk`].
+pub struct FastaWriter<W: Write> {
+    writer: BufWriter<Encoder<W>>,
+    line_width: Option<usize>,
+    record: Vec<u8>,
+}
+
+impl FastaWriter<File> {
+    /// Create a file, compressed according to its extension (`.gz`, `.bgz`, `.zst`)
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let compression = Compression::from_path(&path);
+        Self::with_compression(File::create(path)?, compression)
+    }
+}
+
+impl<W: Write> FastaWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_compression(writer, Compression::None).expect("Uncompressed writer cannot fail")
+    }
+
+    pub fn with_compression(writer: W, compression: Compression) -> Result<Self, Error> {
+        Ok(Self {
+            writer: BufWriter::with_capacity(DEFAULT_CAPACITY, compression.encoder(writer)?),
+            line_width: Some(DEFAULT_LINE_WIDTH),
+            record: Vec::new(),
+        })
+    }
+
+    /// Number of bases per line, `None` writes every sequence on a single line
+    pub fn with_line_width(mut self, line_width: Option<usize>) -> Self {
+        self.line_width = line_width;
+        self
+    }
+
+    #[inline]
+    pub fn line_width(&self) -> Option<usize> {
+        self.line_width
+    }
+
+    #[inline]
+    pub fn write(&mut self, record: &OwnedFastaRecord) -> Result<(), Error> {
+        self.write_parts(record.head(), record.seq())
+    }
+
+    #[inline]
+    pub fn write_parts(&mut self, head: &[u8], seq: &[u8]) -> Result<(), Error> {
+        self.record.clear();
+        format_record(head, seq, self.line_width, &mut self.record);
+        self.writer.write_all(&self.record)
+    }
+
+    /// Write records that were already formatted with [`format_record`]
+    #[inline]
+    pub fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        self.writer.write_all(chunk)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+
+    /// Flush all buffered records, finish the compressed stream and return the underlying writer
+    pub fn finish(self) -> Result<W, Error> {
+        self.writer.into_inner().map_err(|err| err.into_error())?.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_width() {
+        let mut out = Vec::new();
+        format_record(b">seq1 description", b"ACGTACGTAC", Some(4), &mut out);
+        assert_eq!(out, b">seq1 description\nACGT\nACGT\nAC\n");
+
+        out.clear();
+        format_record(b"seq2", b"ACGTACGT", Some(4), &mut out);
+        assert_eq!(out, b">seq2\nACGT\nACGT\n");
+
+        out.clear();
+        format_record(b"seq3", b"ACGTACGTAC", None, &mut out);
+        assert_eq!(out, b">seq3\nACGTACGTAC\n");
+
+        // Empty sequences are written as a header line only, whatever the width
+        for line_width in [None, Some(0), Some(4)] {
+            out.clear();
+            format_record(b"empty", b"", line_width, &mut out);
+            assert_eq!(out, b">empty\n");
+        }
+    }
+
+    #[test]
+    fn test_writer() {
+        let record = OwnedFastaRecord {
+            header: b">chr1".to_vec(),
+            sequence: b"ACGTN".repeat(20),
+        };
+        let mut writer = FastaWriter::new(Vec::new()).with_line_width(Some(70));
+        writer.write(&record).unwrap();
+        writer.write_parts(b"empty", b"").unwrap();
+
+        let written = writer.finish().unwrap();
+        let lines: Vec<&[u8]> = written.split(|&c| c == b'\n').collect();
+        assert_eq!(lines[0], b">chr1");
+        assert_eq!(lines[1].len(), 70);
+        assert_eq!(lines[2].len(), 30);
+        assert_eq!(lines[3], b">empty");
+        assert_eq!(lines[4], b"");
+    }
+}
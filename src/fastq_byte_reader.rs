@@ -1,13 +1,19 @@
-use memchr::{memchr_iter, memmem::Finder};
+use memchr::{memchr, memchr_iter, memmem::Finder, memrchr, memrchr_iter};
 use memmap2::Mmap;
 use std::{
-    fs::File, io::{Error, Read}
+    fs::File, io::Read
 };
 
-
+use crate::error::{BioReaderError, RecordPosition};
 
 pub trait FillBuffer {
-    fn fill_buf(&mut self, buffer: &mut Vec<u8>) -> Result<Option<usize>, Error>;
+    fn fill_buf(&mut self, buffer: &mut Vec<u8>) -> Result<Option<usize>, BioReaderError>;
+
+    /// Position of the first record of the next chunk
+    fn position(&self) -> RecordPosition;
+
+    /// Sequence number of the next chunk, counting from 0
+    fn chunk_index(&self) -> u64;
 }
 
 pub trait FillBufferPair {
@@ -15,7 +21,155 @@ pub trait FillBufferPair {
         &mut self,
         buffer1: &mut Vec<u8>,
         buffer2: &mut Vec<u8>,
-    ) -> Result<Option<(usize, usize)>, Error>;
+    ) -> Result<Option<(usize, usize)>, BioReaderError>;
+
+    /// Positions of the first records of the next chunks in both files
+    fn positions(&self) -> (RecordPosition, RecordPosition);
+
+    /// Sequence number of the next pair of chunks, counting from 0
+    fn chunk_index(&self) -> u64;
+}
+
+/// Chunk of complete records filled by [`FillBuffer::fill_buf`] on another thread
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub buffer: Vec<u8>,
+    /// Number of bytes of `buffer` that belong to the chunk
+    pub fill: usize,
+    /// Position of the first record of the chunk
+    pub position: RecordPosition,
+    pub index: u64,
+}
+
+/// Number of records in a chunk of complete FASTQ records
+#[inline]
+pub(crate) fn count_fastq_records(chunk: &[u8]) -> usize {
+    let lines = memchr_iter(b'\n', chunk).count() + (chunk.last() != Some(&b'\n')) as usize;
+    lines / 4
+}
+
+/// Whether the line starting at `start` is a record header.
+///
+/// '@' also starts quality lines, but only a header has a separator line
+/// starting with '+' two lines below it and the separator of the previous record
+/// two lines above it. For a quality line, these are the sequence lines of the
+/// next record and of its own record. The lines below are checked if the buffer
+/// holds them, `buffer` has to start at a record.
+#[inline]
+pub(crate) fn is_header(buffer: &[u8], start: usize) -> bool {
+    if start == 0 || buffer.get(start) != Some(&b'@') {
+        return false;
+    }
+    let below = memchr(b'\n', &buffer[start..])
+        .map(|pos| start + pos + 1)
+        .and_then(|seq| memchr(b'\n', &buffer[seq..]).map(|pos| seq + pos + 1));
+    if let Some(&byte) = below.and_then(|sep| buffer.get(sep)) {
+        return byte == b'+';
+    }
+    let Some(above_end) = memrchr(b'\n', &buffer[..start - 1]) else {
+        return false;
+    };
+    let above = memrchr(b'\n', &buffer[..above_end]).map_or(0, |pos| pos + 1);
+    buffer[above] == b'+'
+}
+
+/// Double the size of a buffer, but not beyond `max_buffer_size`
+pub(crate) fn grow_buffer(buffer: &mut Vec<u8>, max_buffer_size: Option<usize>, position: RecordPosition) -> Result<(), BioReaderError> {
+    let mut size = std::cmp::max(buffer.len() * 2, 1);
+    if let Some(max_buffer_size) = max_buffer_size {
+        if buffer.len() >= max_buffer_size {
+            return Err(BioReaderError::RecordTooLarge { max_buffer_size, position });
+        }
+        size = std::cmp::min(size, max_buffer_size);
+    }
+    buffer.resize(size, 0);
+    Ok(())
+}
+
+/// Line starting at `start`: end of its content without line break and start of the next line
+#[inline]
+fn line_at(buffer: &[u8], start: usize, at_eof: bool) -> Option<(usize, usize)> {
+    let (end, next) = match memchr(b'\n', &buffer[start..]) {
+        Some(pos) => (start + pos, start + pos + 1),
+        // The last line of a file may lack its line break
+        None if at_eof && start < buffer.len() => (buffer.len(), buffer.len()),
+        None => return None,
+    };
+    let carriage_return = end > start && buffer[end - 1] == b'\r';
+    Some((end - carriage_return as usize, next))
+}
+
+/// End of the possibly wrapped FASTQ record at the start of `buffer`, `None` if it is incomplete.
+///
+/// Sequence lines run until the line starting with '+', quality lines until
+/// they are at least as long as the sequence.
+pub(crate) fn wrapped_record_end(buffer: &[u8], at_eof: bool, position: RecordPosition) -> Result<Option<usize>, BioReaderError> {
+    match buffer.first() {
+        None => return Ok(None),
+        Some(b'@') => {}
+        Some(&byte) => return Err(BioReaderError::InvalidByte { byte, position }),
+    }
+    let Some((_, mut start)) = line_at(buffer, 0, at_eof) else {
+        return Ok(None);
+    };
+
+    let mut sequence = 0;
+    loop {
+        let Some((end, next)) = line_at(buffer, start, at_eof) else {
+            return Ok(None);
+        };
+        let separator = buffer[start] == b'+';
+        if !separator {
+            sequence += end - start;
+        }
+        start = next;
+        if separator {
+            break;
+        }
+    }
+
+    let mut quality = 0;
+    loop {
+        let Some((end, next)) = line_at(buffer, start, at_eof) else {
+            // An empty quality line at the end of the file may lack its line break
+            return Ok((at_eof && sequence == 0 && start == buffer.len()).then_some(start));
+        };
+        quality += end - start;
+        start = next;
+        if quality >= sequence {
+            return Ok(Some(start));
+        }
+    }
+}
+
+/// Append a record found by [`wrapped_record_end`] with its sequence and quality on one line each
+pub(crate) fn compact_record(record: &[u8], out: &mut Vec<u8>) {
+    let line = |start| line_at(record, start, true).expect("Record was checked by wrapped_record_end");
+    let (header_end, mut start) = line(0);
+    let line_break: &[u8] = if start - header_end == 2 { b"\r\n" } else { b"\n" };
+    out.extend_from_slice(&record[..header_end]);
+    out.extend_from_slice(line_break);
+
+    loop {
+        let (end, next) = line(start);
+        let separator = record[start] == b'+';
+        if separator {
+            out.extend_from_slice(line_break);
+        }
+        out.extend_from_slice(&record[start..end]);
+        start = next;
+        if separator {
+            break;
+        }
+    }
+    out.extend_from_slice(line_break);
+
+    while start < record.len() {
+        let (end, next) = line(start);
+        out.extend_from_slice(&record[start..end]);
+        start = next;
+    }
+    out.extend_from_slice(line_break);
 }
 
 pub struct ByteReaderMmap {
@@ -23,100 +177,86 @@ pub struct ByteReaderMmap {
     buffer_fill: usize,
     position: usize,
     record_finder: Finder<'static>,
+    max_buffer_size: Option<usize>,
+    chunk_position: RecordPosition,
+    chunk_index: u64,
 }
 
 impl ByteReaderMmap {
-    pub fn with_capacity(file: &File, buffer_size: usize) -> Result<ByteReaderMmap, Error> {
+    pub fn with_capacity(file: &File, buffer_size: usize) -> Result<ByteReaderMmap, BioReaderError> {
         let br = ByteReaderMmap {
-            mmap: unsafe { Mmap::map(file).expect("Error mapping file") },
+            mmap: unsafe { Mmap::map(file)? },
             buffer_fill: buffer_size,
             position: 0,
             record_finder: Finder::new("\n@"),
+            max_buffer_size: None,
+            chunk_position: RecordPosition::default(),
+            chunk_index: 0,
         };
         Ok(br)
     }
+
+    /// Fail with [`BioReaderError::RecordTooLarge`] instead of returning chunks larger than `max_buffer_size`
+    pub fn with_max_buffer_size(mut self, max_buffer_size: Option<usize>) -> Self {
+        self.max_buffer_size = max_buffer_size;
+        self
+    }
 }
 
 impl FillBuffer for ByteReaderMmap {
-    fn fill_buf(&mut self, buffer: &mut Vec<u8>) -> Result<Option<usize>, Error> {
+    fn position(&self) -> RecordPosition {
+        self.chunk_position
+    }
+
+    fn chunk_index(&self) -> u64 {
+        self.chunk_index
+    }
+
+    fn fill_buf(&mut self, buffer: &mut Vec<u8>) -> Result<Option<usize>, BioReaderError> {
         if self.position >= self.mmap.len() {
             return Ok(None);
         }
 
         if self.position + self.buffer_fill >= self.mmap.len() {
             let chunk_size = self.mmap.len() - self.position;
+            if buffer.len() < chunk_size {
+                buffer.resize(chunk_size, 0);
+            }
             buffer[..chunk_size].copy_from_slice(&self.mmap[self.position..]);
+            self.chunk_position.advance(chunk_size, count_fastq_records(&buffer[..chunk_size]));
+            self.chunk_index += 1;
             self.position = self.mmap.len();
             return Ok(Some(chunk_size));
         }
-        //TODO: If one record does not fit within chunk, what to do?
 
-        let chunk_size = match self
+        // A record crossing the end of the chunk is included completely
+        let remaining = &self.mmap[self.position..];
+        let chunk_size = self
             .record_finder
-            .find(&self.mmap[self.position + self.buffer_fill..])
-        {
-            Some(pos) => self.buffer_fill + pos + 1,
-            None => self.mmap.len(),
-        };
-
-        if chunk_size > 2 * self.buffer_fill {
-            panic!("Record does not fit within buffer size for ByteReaderMMap");
+            .find_iter(&remaining[self.buffer_fill..])
+            .map(|pos| self.buffer_fill + pos + 1)
+            .find(|&start| is_header(remaining, start))
+            .unwrap_or(remaining.len());
+
+        if let Some(max_buffer_size) = self.max_buffer_size {
+            if chunk_size > max_buffer_size {
+                return Err(BioReaderError::RecordTooLarge { max_buffer_size, position: self.chunk_position });
+            }
         }
 
-        while chunk_size > buffer.len() {
-            buffer.resize(buffer.len() * 2, 0);
+        if buffer.len() < chunk_size {
+            buffer.resize(chunk_size, 0);
         }
 
         buffer[..chunk_size].copy_from_slice(&self.mmap[self.position..self.position + chunk_size]);
+        self.chunk_position.advance(chunk_size, count_fastq_records(&buffer[..chunk_size]));
+        self.chunk_index += 1;
         self.position += chunk_size;
 
         Ok(Some(chunk_size))
     }
 }
 
-// impl<T: std::io::Read> FillBuffer for ByteReader<T> {
-//     fn fill_buf(&mut self, buffer: &mut Vec<u8>) -> Result<Option<usize>, Error> {
-//         // Implies that file has been read to the end, but there still might be data in the buffer
-//         if self.finished {
-//             if self.buffer_fill == 0 {
-//                 return Ok(None);
-//             } else if self.buffer_fill <= buffer.len() {
-//                 buffer[..self.buffer_fill].copy_from_slice(&self.buffer[..self.buffer_fill]);
-//                 let fill = self.buffer_fill;
-//                 self.buffer_fill = 0;
-//                 return Ok(Some(fill));
-//             } // File has been read and buffer is empty
-//         }
-
-//         // Find record in slice, at this point buffer must be filled to the brim
-//         assert!(self.buffer_fill >= buffer.len());
-
-//         let buffer_slice = &self.buffer[..buffer.len()];
-
-//         // Find end of last complete Fastq record in local buffer
-//         let mut index = 0;
-//         for (i, &c) in buffer_slice.iter().enumerate().rev() {
-//             if i > 1 && c == b'@' && buffer_slice[i - 1] == b'\n' && buffer_slice[i - 2] != b'+' {
-//                 index = i;
-//                 break;
-//             }
-//         }
-
-//         // Copy local buffer of complete Fastq records into external buffer
-//         buffer[..index].copy_from_slice(&buffer_slice[..index]);
-
-//         self.buffer.drain(0..index);
-//         self.buffer_fill -= index; // unprocessed bytes
-//         self.buffer.resize(self.buffer.capacity(), 0); // restore buffer fill
-
-//         if self.buffer_fill < self.buffer.len() {
-//             self.read()?;
-//         }
-
-//         Ok(Some(index))
-//     }
-// }
-
 pub struct FastqByteReader<T>
 where
     T: std::io::Read,
@@ -125,70 +265,140 @@ where
     buffer_fill: usize,
     buffer: Vec<u8>,
     finished: bool,
+    max_buffer_size: Option<usize>,
+    multiline: bool,
+    chunk_position: RecordPosition,
+    chunk_index: u64,
 }
 
-impl<T: std::io::Read> std::io::Read for FastqByteReader<T> {
-    
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        // Implies that file has been read to the end, but there still might be data in the buffer
-        if self.finished {
-            if self.buffer_fill == 0 {
-                return Ok(0);
-            } else if self.buffer_fill <= buf.len() {
-                buf[..self.buffer_fill].copy_from_slice(&self.buffer[..self.buffer_fill]);
-                let fill = self.buffer_fill;
-                self.buffer_fill = 0;
-                return Ok(fill);
-            } // File has been read and buffer is empty
-        }
+impl<T: std::io::Read> FillBuffer for FastqByteReader<T> {
+    fn position(&self) -> RecordPosition {
+        self.chunk_position
+    }
 
-        // Find record in slice, at this point buffer must be filled to the brim
-        // assert!(self.buffer_fill >= buf.len());
+    fn chunk_index(&self) -> u64 {
+        self.chunk_index
+    }
 
-        let buffer_slice = &self.buffer[..self.buffer_fill];
+    fn fill_buf(&mut self, buf: &mut Vec<u8>) -> Result<Option<usize>, BioReaderError> {
+        // File has been read and buffer is empty
+        if self.finished && self.buffer_fill == 0 {
+            return Ok(None);
+        }
+        if self.multiline {
+            return self.fill_buf_multiline(buf);
+        }
 
-        // Find end of last complete Fastq record in local buffer
-        let mut index = 0;
-        for (i, &c) in buffer_slice.iter().enumerate().rev() {
-            if i > 1 && c == b'@' && buffer_slice[i - 1] == b'\n' && buffer_slice[i - 2] != b'+' {
-                index = i;
-                break;
-            }
+        // Find end of last complete Fastq record in local buffer, grow the buffer
+        // until it holds at least one record
+        let mut index = self.find_next();
+        while index == 0 {
+            grow_buffer(&mut self.buffer, self.max_buffer_size, self.chunk_position)?;
+            self.read()?;
+            index = self.find_next();
         }
 
         // Copy local buffer of complete Fastq records into external buffer
-        buf[..index].copy_from_slice(&buffer_slice[..index]);
-        // println!("- Bytes: {index} {}", buf.len());
-        // println!("- End: {}", std::str::from_utf8(&buf[index-200..index]).unwrap());
+        if buf.len() < index {
+            buf.resize(self.buffer.len(), 0);
+        }
+        buf[..index].copy_from_slice(&self.buffer[..index]);
+        self.chunk_position.advance(index, count_fastq_records(&buf[..index]));
+        self.chunk_index += 1;
 
-        self.buffer.drain(0..index);
+        self.buffer.copy_within(index..self.buffer_fill, 0);
         self.buffer_fill -= index; // unprocessed bytes
-        self.buffer.resize(self.buffer.capacity(), 0); // restore buffer fill
-
 
-        if self.buffer_fill < self.buffer.len() {
-            self.read()?;
-        }
+        self.read()?;
 
-        Ok(index)
+        Ok(Some(index))
     }
 }
 
-
-
 impl<T: std::io::Read> FastqByteReader<T> {
-    pub fn new(reader: T, chunk_size: usize) -> Result<FastqByteReader<T>, Error> {
+    pub fn new(reader: T, chunk_size: usize) -> Result<FastqByteReader<T>, BioReaderError> {
         let mut br = FastqByteReader {
             file: reader,
             buffer_fill: 0,
             buffer: vec![0; chunk_size],
             finished: false,
+            max_buffer_size: None,
+            multiline: false,
+            chunk_position: RecordPosition::default(),
+            chunk_index: 0,
         };
         br.read()?;
         Ok(br)
     }
 
-    pub fn read(&mut self) -> Result<Option<usize>, Error> {
+    /// Limit the growth of the buffer for records larger than the chunk size,
+    /// larger records fail with [`BioReaderError::RecordTooLarge`]
+    pub fn with_max_buffer_size(mut self, max_buffer_size: Option<usize>) -> Self {
+        self.max_buffer_size = max_buffer_size;
+        self
+    }
+
+    /// Accept records with wrapped sequence and quality lines. Chunks are cut by
+    /// parsing every record and hold the records with one sequence and one quality
+    /// line each, byte offsets within a chunk refer to these compacted records.
+    pub fn with_multiline(mut self, multiline: bool) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    fn fill_buf_multiline(&mut self, buf: &mut Vec<u8>) -> Result<Option<usize>, BioReaderError> {
+        let capacity = buf.len();
+        buf.clear();
+        let (mut consumed, mut records) = (0, 0);
+        loop {
+            let buffer = &self.buffer[..self.buffer_fill];
+            while let Some(end) =
+                wrapped_record_end(&buffer[consumed..], self.finished, self.chunk_position.in_chunk(consumed, records as u64))?
+            {
+                compact_record(&buffer[consumed..consumed + end], buf);
+                consumed += end;
+                records += 1;
+            }
+            if records > 0 || self.finished {
+                break;
+            }
+            grow_buffer(&mut self.buffer, self.max_buffer_size, self.chunk_position)?;
+            self.read()?;
+        }
+        // A truncated record at the end of the file is passed on for the reader to report
+        if records == 0 {
+            buf.extend_from_slice(&self.buffer[..self.buffer_fill]);
+            consumed = self.buffer_fill;
+        }
+
+        let fill = buf.len();
+        buf.resize(std::cmp::max(fill, capacity), 0);
+        self.chunk_position.advance(consumed, records);
+        self.chunk_index += 1;
+
+        self.buffer.copy_within(consumed..self.buffer_fill, 0);
+        self.buffer_fill -= consumed;
+
+        self.read()?;
+
+        Ok(Some(fill))
+    }
+
+    /// End of the last complete record in the buffer, 0 if there is none
+    fn find_next(&self) -> usize {
+        // All remaining records are complete at the end of the file
+        if self.finished {
+            return self.buffer_fill;
+        }
+
+        let buffer_slice = &self.buffer[..self.buffer_fill];
+        memrchr_iter(b'\n', buffer_slice)
+            .map(|pos| pos + 1)
+            .find(|&start| is_header(buffer_slice, start))
+            .unwrap_or(0)
+    }
+
+    pub fn read(&mut self) -> Result<Option<usize>, BioReaderError> {
         if self.finished {
             return Ok(None);
         }
@@ -196,7 +406,7 @@ impl<T: std::io::Read> FastqByteReader<T> {
         loop {
             let n_bytes = self.file.read(&mut self.buffer[self.buffer_fill..])?;
             self.buffer_fill += n_bytes;
-            if n_bytes == 0 || self.buffer_fill == self.buffer.capacity() {
+            if n_bytes == 0 || self.buffer_fill == self.buffer.len() {
                 self.finished = n_bytes == 0;
                 break
             }
@@ -220,38 +430,79 @@ where
     buffer2_fill: usize,
     finished1: bool,
     finished2: bool,
+    max_buffer_size: Option<usize>,
+    multiline: bool,
+    chunk_position1: RecordPosition,
+    chunk_position2: RecordPosition,
+    chunk_index: u64,
 }
 
 impl<T: Read> FillBufferPair for FastqPairedByteReader<T> {
+    fn positions(&self) -> (RecordPosition, RecordPosition) {
+        (self.chunk_position1, self.chunk_position2)
+    }
+
+    fn chunk_index(&self) -> u64 {
+        self.chunk_index
+    }
+
     fn fill_buf(
         &mut self,
         buffer1: &mut Vec<u8>,
         buffer2: &mut Vec<u8>,
-    ) -> Result<Option<(usize, usize)>, Error> {
+    ) -> Result<Option<(usize, usize)>, BioReaderError> {
         self.read()?;
 
         if self.done() {
             return Ok(None);
         };
 
-        let (_lines, pos1, pos2) = self.byte_pos().expect("msg");
+        if self.invalid() {
+            let position = match self.buffer1_fill {
+                0 => self.chunk_position2,
+                _ => self.chunk_position1,
+            };
+            return Err(BioReaderError::UnpairedReads { position });
+        }
+        if self.multiline {
+            return self.fill_buf_multiline(buffer1, buffer2);
+        }
+
+        let (pos1, pos2) = loop {
+            if let Some(pos) = self.byte_pos() {
+                break pos;
+            }
+            // Remaining records are complete at the end of the files
+            if self.finished1 && self.finished2 {
+                break (self.buffer1_fill, self.buffer2_fill);
+            }
+            // A full buffer does not hold a complete record
+            if self.buffer1_fill == self.buffer1.len() {
+                grow_buffer(&mut self.buffer1, self.max_buffer_size, self.chunk_position1)?;
+            }
+            if self.buffer2_fill == self.buffer2.len() {
+                grow_buffer(&mut self.buffer2, self.max_buffer_size, self.chunk_position2)?;
+            }
+            self.fill_both_buffs()?;
+        };
 
-        assert!(_lines > 0);
+        if buffer1.len() < pos1 {
+            buffer1.resize(self.buffer1.len(), 0);
+        }
+        if buffer2.len() < pos2 {
+            buffer2.resize(self.buffer2.len(), 0);
+        }
 
-        assert_eq!(self.buffer1[0], b'@');
         buffer1[..pos1].copy_from_slice(&self.buffer1[..pos1]);
-        self.buffer1.drain(0..pos1);
+        self.buffer1.copy_within(pos1..self.buffer1_fill, 0);
         self.buffer1_fill -= pos1;
-        self.buffer1.resize(self.buffer1.capacity(), 0); // restore buffer fill
-        assert_eq!(buffer1[0], b'@');
-
+        self.chunk_position1.advance(pos1, count_fastq_records(&buffer1[..pos1]));
 
-        assert_eq!(self.buffer2[0], b'@');
         buffer2[..pos2].copy_from_slice(&self.buffer2[..pos2]);
-        self.buffer2.drain(0..pos2);
+        self.buffer2.copy_within(pos2..self.buffer2_fill, 0);
         self.buffer2_fill -= pos2;
-        self.buffer2.resize(self.buffer2.capacity(), 0); // restore buffer fill
-        assert_eq!(buffer2[0], b'@');
+        self.chunk_position2.advance(pos2, count_fastq_records(&buffer2[..pos2]));
+        self.chunk_index += 1;
 
 
         Ok(Some((pos1, pos2)))
@@ -263,33 +514,18 @@ impl<T: Read> FastqPairedByteReader<T> {
         self.finished1 && self.finished2 && self.buffer1_fill == 0 && self.buffer2_fill == 0
     }
 
-    fn byte_pos(&mut self) -> Option<(usize, usize, usize)> {
-        let search = self.buffer1_fill > 0
-            || self.buffer2_fill > 0;
-
-        let mut lines = 0;
-        let pos1;
-        let pos2;
-
-
-        if search {
-            lines = self.load_lines()?;
-            pos1 = memchr_iter(b'\n', &self.buffer1).take(lines).last()?;
-            pos2 = memchr_iter(b'\n', &self.buffer2).take(lines).last()?;
-        } else {
-            assert!(self.buffer1_fill > 0);
-            assert!(self.buffer2_fill > 0);
-            pos1 = self.buffer1_fill - 1;
-            pos2 = self.buffer2_fill - 1;
-        }
-        Some((lines, pos1 + 1, pos2 + 1))
+    /// End of the same number of complete records in both buffers
+    fn byte_pos(&self) -> Option<(usize, usize)> {
+        let lines = self.load_lines()?;
+        let pos1 = memchr_iter(b'\n', &self.buffer1[..self.buffer1_fill]).nth(lines - 1)?;
+        let pos2 = memchr_iter(b'\n', &self.buffer2[..self.buffer2_fill]).nth(lines - 1)?;
+        Some((pos1 + 1, pos2 + 1))
     }
 
-    fn load_lines(&mut self) -> Option<usize> {
-        let lines1 = memchr_iter(b'\n', &self.buffer1).count() & !0b11;
-        let lines2 = memchr_iter(b'\n', &self.buffer2).count() & !0b11;
+    fn load_lines(&self) -> Option<usize> {
+        let lines1 = memchr_iter(b'\n', &self.buffer1[..self.buffer1_fill]).count() & !0b11;
+        let lines2 = memchr_iter(b'\n', &self.buffer2[..self.buffer2_fill]).count() & !0b11;
 
-        // println!("Lines: {} == {}", lines1, lines2);
         let min = std::cmp::min(lines1, lines2);
         if min > 0 {
             Some(min)
@@ -304,75 +540,295 @@ impl<T: Read> FastqPairedByteReader<T> {
         buff_capacity: usize,
     ) -> Self {
         FastqPairedByteReader {
-            file1: file1,
-            file2: file2,
+            file1,
+            file2,
             buffer1_fill: 0,
             buffer2_fill: 0,
             buffer1: vec![0; buff_capacity],
             buffer2: vec![0; buff_capacity],
             finished1: false,
             finished2: false,
+            max_buffer_size: None,
+            multiline: false,
+            chunk_position1: RecordPosition::default(),
+            chunk_position2: RecordPosition::default(),
+            chunk_index: 0,
         }
     }
 
+    /// Limit the growth of each buffer for records larger than the chunk size,
+    /// larger records fail with [`BioReaderError::RecordTooLarge`]
+    pub fn with_max_buffer_size(mut self, max_buffer_size: Option<usize>) -> Self {
+        self.max_buffer_size = max_buffer_size;
+        self
+    }
+
+    /// Accept records with wrapped lines, see [`FastqByteReader::with_multiline`]
+    pub fn with_multiline(mut self, multiline: bool) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Both chunks end after the same number of records
+    fn fill_buf_multiline(&mut self, buffer1: &mut Vec<u8>, buffer2: &mut Vec<u8>) -> Result<Option<(usize, usize)>, BioReaderError> {
+        let (capacity1, capacity2) = (buffer1.len(), buffer2.len());
+        buffer1.clear();
+        buffer2.clear();
+        let (mut pos1, mut pos2, mut records) = (0, 0, 0);
+        loop {
+            let (slice1, slice2) = (&self.buffer1[..self.buffer1_fill], &self.buffer2[..self.buffer2_fill]);
+            loop {
+                let position1 = self.chunk_position1.in_chunk(pos1, records as u64);
+                let position2 = self.chunk_position2.in_chunk(pos2, records as u64);
+                let Some(end1) = wrapped_record_end(&slice1[pos1..], self.finished1, position1)? else {
+                    break;
+                };
+                let Some(end2) = wrapped_record_end(&slice2[pos2..], self.finished2, position2)? else {
+                    break;
+                };
+                compact_record(&slice1[pos1..pos1 + end1], buffer1);
+                compact_record(&slice2[pos2..pos2 + end2], buffer2);
+                pos1 += end1;
+                pos2 += end2;
+                records += 1;
+            }
+            if records > 0 || (self.finished1 && self.finished2) {
+                break;
+            }
+            if self.buffer1_fill == self.buffer1.len() {
+                grow_buffer(&mut self.buffer1, self.max_buffer_size, self.chunk_position1)?;
+            }
+            if self.buffer2_fill == self.buffer2.len() {
+                grow_buffer(&mut self.buffer2, self.max_buffer_size, self.chunk_position2)?;
+            }
+            self.fill_both_buffs()?;
+        }
+        // Truncated or unpaired records at the end of the files are passed on for the reader to report
+        if records == 0 {
+            buffer1.extend_from_slice(&self.buffer1[..self.buffer1_fill]);
+            buffer2.extend_from_slice(&self.buffer2[..self.buffer2_fill]);
+            (pos1, pos2) = (self.buffer1_fill, self.buffer2_fill);
+        }
+
+        let (fill1, fill2) = (buffer1.len(), buffer2.len());
+        buffer1.resize(std::cmp::max(fill1, capacity1), 0);
+        buffer2.resize(std::cmp::max(fill2, capacity2), 0);
+
+        self.buffer1.copy_within(pos1..self.buffer1_fill, 0);
+        self.buffer1_fill -= pos1;
+        self.chunk_position1.advance(pos1, records);
+        self.buffer2.copy_within(pos2..self.buffer2_fill, 0);
+        self.buffer2_fill -= pos2;
+        self.chunk_position2.advance(pos2, records);
+        self.chunk_index += 1;
+
+        Ok(Some((fill1, fill2)))
+    }
+
     pub fn invalid(&mut self) -> bool {
         (self.buffer1_fill == 0 || self.buffer2_fill == 0) && self.buffer1_fill != self.buffer2_fill
     }
 
-    pub fn fill_both_buffs(&mut self) -> Result<(), Error> {
-        // println!("Fill buffs: {} {} {} {}", self.buffer1_fill, self.buffer2_fill, self.finished1, self.finished2);
-
-        if self.buffer1_fill < self.buffer1.capacity() {
+    pub fn fill_both_buffs(&mut self) -> Result<(), BioReaderError> {
+        if self.buffer1_fill < self.buffer1.len() {
             // GzDecoder does not read full buffer but only chunks so we need to loop
             // to Fill the buffer
             loop {
                 let n_bytes1 = self.file1.read(&mut self.buffer1[self.buffer1_fill..])?;
                 self.buffer1_fill += n_bytes1;
-                if n_bytes1 == 0 || self.buffer1_fill == self.buffer1.capacity() {
+                if n_bytes1 == 0 || self.buffer1_fill == self.buffer1.len() {
                     self.finished1 = n_bytes1 == 0;
                     break
                 }
             }
-            let n_bytes1 = self.file1.read(&mut self.buffer1[self.buffer1_fill..])?;
-            self.buffer1_fill += n_bytes1;
         }
 
-        if self.buffer2_fill < self.buffer2.capacity() {
+        if self.buffer2_fill < self.buffer2.len() {
             // GzDecoder does not read full buffer but only chunks so we need to loop
             // to Fill the buffer
             loop {
                 let n_bytes2 = self.file2.read(&mut self.buffer2[self.buffer2_fill..])?;
                 self.buffer2_fill += n_bytes2;
-                if n_bytes2 == 0 || self.buffer2_fill == self.buffer2.capacity() {
+                if n_bytes2 == 0 || self.buffer2_fill == self.buffer2.len() {
                     self.finished2 = n_bytes2 == 0;
                     break
                 }
             }
         }
 
-        if self.done() { return Ok(()); };
-
-        assert_eq!(self.buffer1[0], b'@');
-        assert_eq!(self.buffer2[0], b'@');
-
-        assert!(self.done() || (self.buffer1[0] == b'@' && self.buffer2[0] == b'@'));
         Ok(())
     }
 
-    pub fn read(&mut self) -> Result<Option<()>, Error> {
+    pub fn read(&mut self) -> Result<Option<()>, BioReaderError> {
         if self.finished1 && self.finished2 {
             return Ok(None);
         }
 
-        let _ = self.fill_both_buffs();
+        self.fill_both_buffs()?;
 
-        if self.invalid() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Fastq files of different length.",
-            ));
+        Ok(Some(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::fastq_reader::{FastqReader, PairedFastqReader};
+
+    fn fastq(long_len: usize) -> Vec<u8> {
+        let long = "A".repeat(long_len);
+        let qual = "I".repeat(long_len);
+        format!("@short1\nACGT\n+\nIIII\n@long\n{long}\n+\n{qual}\n@short2\nTT\n+\nII\n").into_bytes()
+    }
+
+    fn seq_lens<B: FillBuffer>(byte_reader: &mut B) -> Result<Vec<usize>, BioReaderError> {
+        let mut reader = FastqReader::with_capacity(16);
+        let mut lens = Vec::new();
+        while let Some(()) = reader.load_batch(byte_reader)? {
+            while let Some(record) = reader.next()? {
+                lens.push(record.seq().len());
+            }
         }
+        Ok(lens)
+    }
 
-        Ok(Some(()))
+    #[test]
+    fn test_record_larger_than_buffer() {
+        let mut byte_reader = FastqByteReader::new(Cursor::new(fastq(5000)), 16).unwrap();
+        assert_eq!(seq_lens(&mut byte_reader).unwrap(), vec![4, 5000, 2]);
+
+        let mut byte_reader = FastqByteReader::new(Cursor::new(fastq(5000)), 16)
+            .unwrap()
+            .with_max_buffer_size(Some(1000));
+        let err = seq_lens(&mut byte_reader).unwrap_err();
+        assert!(matches!(
+            err,
+            BioReaderError::RecordTooLarge { max_buffer_size: 1000, position } if position.record_number == 2
+        ));
+    }
+
+    #[test]
+    fn test_mmap_record_larger_than_buffer() {
+        let file = File::open("data/fastq/small_test_1.fq").unwrap();
+        let mut byte_reader = ByteReaderMmap::with_capacity(&file, 16).unwrap();
+        assert_eq!(seq_lens(&mut byte_reader).unwrap().len(), 10000);
+
+        let mut byte_reader = ByteReaderMmap::with_capacity(&file, 16).unwrap().with_max_buffer_size(Some(100));
+        assert!(seq_lens(&mut byte_reader).is_err());
+    }
+
+    #[test]
+    fn test_mmap_error() {
+        // Directories cannot be mapped, the error is returned instead of panicking
+        let dir = File::open("data").unwrap();
+        assert!(matches!(ByteReaderMmap::with_capacity(&dir, 16), Err(BioReaderError::Io(_))));
+    }
+
+    #[test]
+    fn test_paired_record_larger_than_buffer() {
+        let byte_reader = FastqPairedByteReader::new(Cursor::new(fastq(3000)), Cursor::new(fastq(10)), 16);
+        let mut reader = PairedFastqReader::new(Arc::new(Mutex::new(byte_reader)), 16);
+        let mut lens = Vec::new();
+        while let Some((r1, r2)) = reader.next_pair().unwrap() {
+            lens.push((r1.seq().len(), r2.seq().len()));
+        }
+        assert_eq!(lens, vec![(4, 4), (3000, 10), (2, 2)]);
+
+        let mut byte_reader = FastqPairedByteReader::new(Cursor::new(fastq(3000)), Cursor::new(fastq(10)), 16)
+            .with_max_buffer_size(Some(1024));
+        let (mut buffer1, mut buffer2) = (vec![0; 16], vec![0; 16]);
+        let mut result = Ok(Some((0, 0)));
+        while let Ok(Some(_)) = result {
+            result = byte_reader.fill_buf(&mut buffer1, &mut buffer2);
+        }
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_adversarial_quality() {
+        // Quality lines that look like headers, after separators with and without a name
+        let records: Vec<(String, String)> = (0..200)
+            .map(|i| {
+                let seq = "ACGTN".repeat(i % 7 + 1);
+                let qual: String = (0..seq.len()).map(|j| ['@', '+', 'I', '@'][(i + j) % 4]).collect();
+                (seq, format!("@{}", &qual[1..]))
+            })
+            .collect();
+        let fastq: String = records
+            .iter()
+            .enumerate()
+            .map(|(i, (seq, qual))| match i % 3 {
+                0 => format!("@read{i}\n{seq}\n+\n{qual}\n"),
+                1 => format!("@read{i}\n{seq}\n+read{i}\n{qual}\n"),
+                _ => format!("@read{i}\r\n{seq}\r\n+read{i}\r\n{qual}\r\n"),
+            })
+            .collect();
+        let path = std::env::temp_dir().join(format!("bioreader_adversarial_{}.fq", std::process::id()));
+        std::fs::write(&path, &fastq).unwrap();
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        fn check<B: FillBuffer>(byte_reader: &mut B, records: &[(String, String)]) {
+            let mut buffer = Vec::new();
+            let mut seen = 0;
+            while let Some(fill) = byte_reader.fill_buf(&mut buffer).unwrap() {
+                let mut reader = FastqReader::with_capacity(0);
+                reader.load_chunk(Chunk { buffer: buffer[..fill].to_vec(), fill, ..Default::default() });
+                while let Some(record) = reader.next().unwrap() {
+                    assert_eq!((record.seq(), record.qual()), (records[seen].0.as_bytes(), records[seen].1.as_bytes()));
+                    seen += 1;
+                }
+            }
+            assert_eq!(seen, records.len());
+        }
+        for chunk_size in (1..64).chain([100, 1000, 1 << 16]) {
+            check(&mut FastqByteReader::new(Cursor::new(fastq.as_bytes()), chunk_size).unwrap(), &records);
+            check(&mut ByteReaderMmap::with_capacity(&file, chunk_size).unwrap(), &records);
+        }
+    }
+
+    #[test]
+    fn test_multiline() {
+        // Quality lines may start with '@' and '+', the last record lacks its final line break
+        let wrapped = "@r1\nACGT\nAC\n+\n@III\n+I\n@r2 empty\n+\n\n@r3\r\nAC\r\nGT\r\n+r3\r\n@@\r\n@@\r\n@r4\nA\nC\nG\n+\nI\n@\n+";
+        let expected = [("ACGTAC", "@III+I"), ("", ""), ("ACGT", "@@@@"), ("ACG", "I@+")];
+        let records = |reader: &mut FastqReader, byte_reader: &mut FastqByteReader<Cursor<&str>>| {
+            let mut records = Vec::new();
+            while let Some(()) = reader.load_batch(byte_reader).unwrap() {
+                while let Some(record) = reader.next().unwrap() {
+                    records.push((String::from_utf8(record.seq().to_vec()).unwrap(), String::from_utf8(record.qual().to_vec()).unwrap()));
+                }
+            }
+            records
+        };
+
+        for chunk_size in [4, 16, 1 << 10] {
+            let mut byte_reader = FastqByteReader::new(Cursor::new(wrapped), chunk_size).unwrap().with_multiline(true);
+            let records = records(&mut FastqReader::with_capacity(chunk_size), &mut byte_reader);
+            assert_eq!(records, expected.map(|(seq, qual)| (seq.to_string(), qual.to_string())));
+            assert_eq!(byte_reader.position().record_number, 5);
+            assert_eq!(byte_reader.position().byte_offset, wrapped.len() as u64);
+
+            let byte_reader = FastqPairedByteReader::new(Cursor::new(wrapped), Cursor::new(wrapped), chunk_size).with_multiline(true);
+            let mut reader = PairedFastqReader::new(Arc::new(Mutex::new(byte_reader)), chunk_size);
+            let mut pairs = 0;
+            while let Some((record1, record2)) = reader.next_pair().unwrap() {
+                assert_eq!((record1.seq(), record1.qual()), (expected[pairs].0.as_bytes(), expected[pairs].1.as_bytes()));
+                assert_eq!(record1.qual(), record2.qual());
+                pairs += 1;
+            }
+            assert_eq!(pairs, 4);
+        }
+
+        // Without the option, wrapped records are malformed
+        let mut byte_reader = FastqByteReader::new(Cursor::new(wrapped), 1 << 10).unwrap();
+        let mut reader = FastqReader::with_capacity(1 << 10);
+        reader.load_batch(&mut byte_reader).unwrap();
+        assert!(reader.next().is_err());
     }
 }
@@ -1,111 +1,217 @@
 use memchr::memchr;
-use std::{io::{Error, Read}, sync::{Arc, Mutex}};
+use lending_iterator::prelude::*;
+use std::{io::Read, path::Path, sync::{Arc, Mutex}};
 
 // use memmap2::Mmap;
 
-use crate::{fastq_byte_reader::{FillBufferPair, FastqPairedByteReader}, sequence::fastq_record::{BufferPosition, RefFastqRecord}};
+use crate::{compression::{open, DynRead}, error::{BioReaderError, RecordPosition}, fastq_byte_reader::{Chunk, FastqByteReader, FillBuffer, FillBufferPair, FastqPairedByteReader}, format::DEFAULT_CHUNK_SIZE, sequence::fastq_record::{BufferPosition, OwnedFastqRecord, RefFastqRecord}};
 
-#[cfg(windows)]
-const LINE_ENDING: &'static str = "\r\n";
-#[cfg(not(windows))]
-const LINE_ENDING: &'static str = "\n";
+/// Locate the lines of the record starting at `buffer_pos.pos.1`.
+///
+/// `buffer` must end after the last complete record of the chunk, `position`
+/// is the position of the record in the file and only used for errors.
+pub(crate) fn find_record(buffer: &[u8], buffer_pos: &mut BufferPosition, position: RecordPosition) -> Result<(), BioReaderError> {
+    let truncated = || BioReaderError::TruncatedRecord { position };
+    let start = buffer_pos.pos.1;
 
+    if buffer[start] != b'@' {
+        return Err(BioReaderError::InvalidByte { byte: buffer[start], position });
+    }
+
+    let seq = memchr(b'\n', &buffer[start..]).ok_or_else(truncated)? + start + 1;
+    let sep = memchr(b'\n', &buffer[seq..]).ok_or_else(truncated)? + seq + 1;
+    match buffer.get(sep) {
+        Some(b'+') => {}
+        Some(_) => return Err(BioReaderError::MissingSeparator { position }),
+        None => return Err(truncated()),
+    }
+    let qual = memchr(b'\n', &buffer[sep..]).ok_or_else(truncated)? + sep + 1;
+    // The last record of a file may lack the final newline
+    let end = memchr(b'\n', &buffer[qual..]).map_or(buffer.len(), |pos| pos + qual);
+
+    buffer_pos.pos = (start, end);
+    buffer_pos.seq = seq;
+    buffer_pos.sep = sep;
+    buffer_pos.qual = qual;
+
+    let sequence = buffer_pos.seq(buffer).len();
+    let quality = buffer_pos.qual(buffer).len();
+    if sequence != quality {
+        if end == buffer.len() && quality < sequence {
+            return Err(truncated());
+        }
+        return Err(BioReaderError::LengthMismatch { sequence, quality, position });
+    }
+
+    Ok(())
+}
 
 pub struct PairedFastqReader<T> where T: Read{
-    reader: Arc<Mutex<FastqPairedByteReader<T>>>,
+    // None for readers fed by [`PairedFastqReader::load_chunks`]
+    reader: Option<Arc<Mutex<FastqPairedByteReader<T>>>>,
     pub buffer1: Vec<u8>,
     pub buffer1_fill: usize,
     pub buffer2: Vec<u8>,
     pub buffer2_fill: usize,
     buf1_pos: BufferPosition,
     buf2_pos: BufferPosition,
+    chunk_position1: RecordPosition,
+    chunk_position2: RecordPosition,
+    chunk_index: u64,
+    record_index: u64,
+    // Set by [`PairedFastqReader::next_pair`] after an error
+    failed: bool,
 }
 
 impl<T: Read> PairedFastqReader<T> {
     pub fn new(reader: Arc<Mutex<FastqPairedByteReader<T>>>, capacity: usize) -> Self {
         Self {
-            reader: reader,
+            reader: Some(reader),
+            ..Self::with_capacity(capacity)
+        }
+    }
+
+    /// Reader without a byte reader, its batches are loaded with [`PairedFastqReader::load_chunks`]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            reader: None,
             buffer1: vec![0; capacity],
             buffer1_fill: 0,
             buffer2: vec![0; capacity],
             buffer2_fill: 0,
             buf1_pos: BufferPosition::default(),
             buf2_pos: BufferPosition::default(),
+            chunk_position1: RecordPosition::default(),
+            chunk_position2: RecordPosition::default(),
+            chunk_index: 0,
+            record_index: 0,
+            failed: false,
         }
     }
 
     #[inline]
-    pub fn load_batch_par(&mut self) -> Result<Option<()>, std::io::Error> {
-        
-        match self.reader.lock().expect("Locking ByteReader was unsuccessful")
-                .fill_buf(&mut self.buffer1, &mut self.buffer2)? {
-            
+    pub fn load_batch_par(&mut self) -> Result<Option<()>, BioReaderError> {
+        let Some(reader) = &self.reader else {
+            return Ok(None);
+        };
+        let mut reader = reader.lock().expect("Locking ByteReader was unsuccessful");
+        (self.chunk_position1, self.chunk_position2) = reader.positions();
+        self.chunk_index = reader.chunk_index();
+        self.record_index = 0;
+        self.buf1_pos.reset(0);
+        self.buf2_pos.reset(0);
+
+        match reader.fill_buf(&mut self.buffer1, &mut self.buffer2)? {
             Some((pos1, pos2)) => {
                 self.buffer1_fill = pos1;
                 self.buffer2_fill = pos2;
 
                 Ok(Some(()))
             },
-            None => Ok(None)
+            None => {
+                self.buffer1_fill = 0;
+                self.buffer2_fill = 0;
+                Ok(None)
+            }
         }
     }
 
-    pub fn find_position(buffer: &mut [u8], buffer_pos: &mut BufferPosition) -> Option<()> {
-        if buffer[buffer_pos.pos.1] != b'@' {
-            println!("{}", std::str::from_utf8(&buffer[buffer_pos.pos.0..buffer_pos.pos.1+10]).unwrap());
-        }
-        assert!(buffer[buffer_pos.pos.1] == b'@');
+    /// Take over a pair of chunks filled by another thread, returns the previous buffers for reuse
+    pub fn load_chunks(&mut self, chunk1: Chunk, chunk2: Chunk) -> (Vec<u8>, Vec<u8>) {
+        (self.chunk_position1, self.chunk_position2) = (chunk1.position, chunk2.position);
+        self.chunk_index = chunk1.index;
+        self.record_index = 0;
+        self.buf1_pos.reset(0);
+        self.buf2_pos.reset(0);
+        self.buffer1_fill = chunk1.fill;
+        self.buffer2_fill = chunk2.fill;
+        (
+            std::mem::replace(&mut self.buffer1, chunk1.buffer),
+            std::mem::replace(&mut self.buffer2, chunk2.buffer),
+        )
+    }
 
+    /// Positions of the current pair of records in both files
+    pub fn positions(&self) -> (RecordPosition, RecordPosition) {
+        (
+            self.chunk_position1.in_chunk(self.buf1_pos.pos.0, self.record_index.saturating_sub(1)),
+            self.chunk_position2.in_chunk(self.buf2_pos.pos.0, self.record_index.saturating_sub(1)),
+        )
+    }
 
-        let pos1: usize = memchr(b'\n', &buffer[buffer_pos.pos.1..]).expect("1: Couldn't find newline") + buffer_pos.pos.1+1;
-        let pos2 = memchr(b'\n', &buffer[pos1..]).expect("2: Couldn't find newline ") + pos1+1;
-        
-        assert!(buffer[pos2] == b'+');
-        let pos3  = memchr(b'\n', &buffer[pos2..]).expect("3: Couldn't find newline") + pos2+1;
-        let mut pos4 = memchr(b'\n', &buffer[pos3..]).unwrap_or(buffer.len() - pos3);
-        pos4 += pos3;
+    pub fn find_position(buffer: &[u8], buffer_pos: &mut BufferPosition, position: RecordPosition) -> Result<(), BioReaderError> {
+        find_record(buffer, buffer_pos, position)
+    }
+
+    /// Sequence number of the loaded pair of chunks
+    pub fn chunk_index(&self) -> u64 {
+        self.chunk_index
+    }
+
+    /// True if both buffers have no records left
+    fn batch_done(&self) -> bool {
+        let next1 = self.buf1_pos.pos.1 + (self.buf1_pos.pos.1 > 0) as usize;
+        let next2 = self.buf2_pos.pos.1 + (self.buf2_pos.pos.1 > 0) as usize;
+        next1 >= self.buffer1_fill && next2 >= self.buffer2_fill
+    }
+
+    /// Next pair of records, loading the next batch when the current one is exhausted, `None` after the first error
+    pub fn next_pair(&mut self) -> Result<Option<(RefFastqRecord<'_>, RefFastqRecord<'_>)>, BioReaderError> {
+        if self.failed {
+            return Ok(None);
+        }
+        match self.advance() {
+            Ok(true) => Ok(Some(self.pair())),
+            Ok(false) => Ok(None),
+            Err(err) => {
+                self.failed = true;
+                Err(err)
+            }
+        }
+    }
 
-        buffer_pos.pos = (buffer_pos.pos.1, pos4);
-        buffer_pos.seq = pos1;
-        buffer_pos.sep = pos2;
-        buffer_pos.qual = pos3;
+    /// Next pair of records of the batch loaded by [`PairedFastqReader::load_batch_par`], `None` at its end
+    pub fn next_in_batch(&mut self) -> Result<Option<(RefFastqRecord<'_>, RefFastqRecord<'_>)>, BioReaderError> {
+        Ok(self.advance_in_batch()?.then(|| self.pair()))
+    }
 
-        Some(())
+    fn advance(&mut self) -> Result<bool, BioReaderError> {
+        if self.batch_done() && self.load_batch_par()?.is_none() {
+            return Ok(false);
+        }
+        self.advance_in_batch()
     }
 
-    pub fn next(&mut self) -> Option<(RefFastqRecord, RefFastqRecord)> {
+    /// Move to the next pair of the batch, false at its end
+    fn advance_in_batch(&mut self) -> Result<bool, BioReaderError> {
         self.buf1_pos.pos.1 += (self.buf1_pos.pos.1 > 0) as usize;
         self.buf2_pos.pos.1 += (self.buf2_pos.pos.1 > 0) as usize;
 
         let at_end1 = self.buf1_pos.pos.1 >= self.buffer1_fill;
         let at_end2 = self.buf2_pos.pos.1 >= self.buffer2_fill;
 
-        assert_eq!(at_end1, at_end2);
-        
-        if at_end2 && at_end2 {
-            let load = self.load_batch_par().expect("Valid filestream");
-
-            self.buf1_pos.reset(0);
-            self.buf2_pos.reset(0);
-            if load.is_none() { return None; }
+        if at_end1 != at_end2 {
+            let position = match at_end1 {
+                true => self.chunk_position2.in_chunk(self.buf2_pos.pos.1, self.record_index),
+                false => self.chunk_position1.in_chunk(self.buf1_pos.pos.1, self.record_index),
+            };
+            return Err(BioReaderError::UnpairedReads { position });
         }
 
-
-        if self.buffer1[self.buf1_pos.pos.0] != b'@' {
-            
-            println!("{} {:?}", self.buffer1_fill, self.buf1_pos);
-            println!(">>>>>>{}<<<<<<", std::str::from_utf8(&self.buffer1[0..100]).unwrap());
-            println!("{}", self.buffer1.len());
+        if at_end1 {
+            return Ok(false);
         }
 
-        assert!(self.buffer1[self.buf1_pos.pos.0] == b'@');
-        assert!(self.buffer2[self.buf2_pos.pos.0] == b'@');
-        assert!(self.buf1_pos.pos.0 == 0  || self.buffer1[self.buf1_pos.pos.0-1] == b'\n');
-        assert!(self.buf2_pos.pos.0 == 0  || self.buffer2[self.buf2_pos.pos.0-1] == b'\n');
-
-        PairedFastqReader::<T>::find_position(&mut self.buffer1[..self.buffer1_fill], &mut self.buf1_pos);
-        PairedFastqReader::<T>::find_position(&mut self.buffer2[..self.buffer2_fill], &mut self.buf2_pos);
+        let position1 = self.chunk_position1.in_chunk(self.buf1_pos.pos.1, self.record_index);
+        let position2 = self.chunk_position2.in_chunk(self.buf2_pos.pos.1, self.record_index);
+        find_record(&self.buffer1[..self.buffer1_fill], &mut self.buf1_pos, position1)?;
+        find_record(&self.buffer2[..self.buffer2_fill], &mut self.buf2_pos, position2)?;
+        self.record_index += 1;
+        Ok(true)
+    }
 
+    /// The current pair of records
+    fn pair(&self) -> (RefFastqRecord<'_>, RefFastqRecord<'_>) {
         let r1 = RefFastqRecord {
             buffer: &self.buffer1,
             buf_pos: &self.buf1_pos,
@@ -114,11 +220,9 @@ impl<T: Read> PairedFastqReader<T> {
             buffer: &self.buffer2,
             buf_pos: &self.buf2_pos,
         };
-
-        Some((r1, r2))
+        (r1, r2)
     }
 
-
 }
 
 
@@ -127,13 +231,18 @@ pub struct FastqReader {
     pub buffer: Vec<u8>,
     pub buffer_size: usize,
     buf_pos: BufferPosition,
-    finished: bool,
+    chunk_position: RecordPosition,
+    chunk_index: u64,
+    record_index: u64,
 }
 
+impl Default for FastqReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-
-
-impl<'a> FastqReader { 
+impl FastqReader {
 
     pub fn new() -> FastqReader {
         FastqReader::with_capacity(usize::pow(2, 20))
@@ -144,17 +253,22 @@ impl<'a> FastqReader {
             buffer: vec![0; capacity],
             buffer_size: 0,
             buf_pos: BufferPosition::default(),
-            finished: false,
+            chunk_position: RecordPosition::default(),
+            chunk_index: 0,
+            record_index: 0,
         }
     }
 
     #[inline]
-    pub fn load_batch<T>(&mut self, br: &mut T) -> Result<Option<()>, std::io::Error> where 
-            T: std::io::Read {
+    pub fn load_batch<T>(&mut self, br: &mut T) -> Result<Option<()>, BioReaderError> where 
+            T: FillBuffer {
 
         self.buf_pos.reset(0);
-        match br.read(&mut self.buffer)? {
-            bytes if bytes > 0 => {
+        self.chunk_position = br.position();
+        self.chunk_index = br.chunk_index();
+        self.record_index = 0;
+        match br.fill_buf(&mut self.buffer)? {
+            Some(bytes) if bytes > 0 => {
                 self.buffer_size = bytes;
                 Ok(Some(()))
             },
@@ -163,57 +277,289 @@ impl<'a> FastqReader {
     }
 
     #[inline]
-    pub fn load_batch_par<T>(&mut self, br: &mut Arc<Mutex<T>>) -> Result<Option<()>, std::io::Error> where 
-            T: std::io::Read {
+    pub fn load_batch_par<T>(&mut self, br: &mut Arc<Mutex<T>>) -> Result<Option<()>, BioReaderError> where 
+            T: FillBuffer {
+        let mut br = br.lock().expect("Locking ByteReader was unsuccessful");
+        self.load_batch(&mut *br)
+    }
+
+    /// Take over a chunk filled by another thread, returns the previous buffer for reuse
+    pub fn load_chunk(&mut self, chunk: Chunk) -> Vec<u8> {
         self.buf_pos.reset(0);
-        match br.lock().expect("Locking ByteReader was unsuccessful").read(&mut self.buffer)? {
-            bytes if bytes > 0 => {
-                self.buffer_size = bytes;
-                Ok(Some(()))
-            },
-            _ => Ok(None)
-        }
+        self.chunk_position = chunk.position;
+        self.chunk_index = chunk.index;
+        self.record_index = 0;
+        self.buffer_size = chunk.fill;
+        std::mem::replace(&mut self.buffer, chunk.buffer)
     }
 
-    pub fn next(&mut self) -> Option<RefFastqRecord> {
+    /// Position of the record last returned by [`FastqReader::next`]
+    pub fn position(&self) -> RecordPosition {
+        self.chunk_position.in_chunk(self.buf_pos.pos.0, self.record_index.saturating_sub(1))
+    }
+
+    /// Sequence number of the loaded chunk in its input
+    pub fn chunk_index(&self) -> u64 {
+        self.chunk_index
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<RefFastqRecord<'_>>, BioReaderError> {
         self.buf_pos.pos.1 += (self.buf_pos.pos.1 > 0) as usize;
 
         if self.buf_pos.pos.1 >= self.buffer_size {
-            return None;
+            return Ok(None);
         }
 
+        let position = self.chunk_position.in_chunk(self.buf_pos.pos.1, self.record_index);
+        find_record(&self.buffer[..self.buffer_size], &mut self.buf_pos, position)?;
+        self.record_index += 1;
 
-        assert!(self.buf_pos.pos.0 == 0  || self.buffer[self.buf_pos.pos.0-1] == b'\n');
-        assert!(self.buffer[self.buf_pos.pos.0] == b'@');
-        let pos1: usize = memchr(b'\n', &self.buffer[self.buf_pos.pos.1..self.buffer_size]).expect("Couldn't find newline") + self.buf_pos.pos.1+1;
-        let pos2 = memchr(b'\n', &self.buffer[pos1..self.buffer_size]).expect("Couldn't find newline") + pos1+1;
-        
-        assert!(self.buffer[pos2] == b'+');
-        let pos3  = memchr(b'\n', &self.buffer[pos2..self.buffer_size]).expect("Couldn't find newline") + pos2+1;
-        let mut pos4 = memchr(b'\n', &self.buffer[pos3..self.buffer_size]).unwrap_or(self.buffer_size - pos3);
-        pos4 += pos3;
-
-        self.buf_pos.pos = (self.buf_pos.pos.1, pos4);
-        self.buf_pos.seq = pos1;
-        self.buf_pos.sep = pos2;
-        self.buf_pos.qual = pos3;
-
-        Some(RefFastqRecord {
+        Ok(Some(RefFastqRecord {
             buffer: &self.buffer,
             buf_pos: &self.buf_pos,
-        })
+        }))
     }
 }
 
 
-// // Implement `Iterator` for `Fibonacci`.
-// // The `Iterator` trait only requires a method to be defined for the `next` element.
-// impl<'a> Iterator for FastqReader<'a> {
-//     // We can refer to this type using Self::Item
-//     type Item = RefRecord<'a>;
+impl FastqReader {
+    /// Records of a FASTQ file with any supported compression
+    pub fn from_path(path: impl AsRef<Path>) -> Result<FastqRecords<FastqByteReader<DynRead<'static>>>, BioReaderError> {
+        let byte_reader = FastqByteReader::new(open(path)?, DEFAULT_CHUNK_SIZE)?;
+        Ok(FastqReader::with_capacity(DEFAULT_CHUNK_SIZE).records(byte_reader))
+    }
+
+    /// Records of `byte_reader`, loading its chunks into this reader as needed
+    pub fn records<B: FillBuffer>(self, byte_reader: B) -> FastqRecords<B> {
+        FastqRecords { byte_reader, reader: self, failed: false }
+    }
+
+    /// True if the loaded chunk has no records left
+    fn batch_done(&self) -> bool {
+        self.buf_pos.pos.1 + (self.buf_pos.pos.1 > 0) as usize >= self.buffer_size
+    }
+}
 
-//     fn next(&mut self) -> Option<Self::Item> {
-//         self.next().
-//         Some()
-//     }
-// }
+/// [`LendingIterator`] over the records of a byte reader, see [`FastqRecords::owned`] for an [`Iterator`]
+pub struct FastqRecords<B> {
+    byte_reader: B,
+    reader: FastqReader,
+    // The position within the chunk is unknown after an error
+    failed: bool,
+}
+
+impl<B: FillBuffer> FastqRecords<B> {
+    /// Next record, `None` after the first error
+    pub fn next_record(&mut self) -> Result<Option<RefFastqRecord<'_>>, BioReaderError> {
+        if self.failed {
+            return Ok(None);
+        }
+        while self.reader.batch_done() {
+            match self.reader.load_batch(&mut self.byte_reader) {
+                Ok(Some(())) => {}
+                Ok(None) => return Ok(None),
+                Err(err) => {
+                    self.failed = true;
+                    return Err(err);
+                }
+            }
+        }
+        let record = self.reader.next();
+        self.failed = record.is_err();
+        record
+    }
+
+    /// Position of the record last returned
+    pub fn position(&self) -> RecordPosition {
+        self.reader.position()
+    }
+
+    /// Iterator over copies of the records
+    pub fn owned(self) -> OwnedFastqRecords<B> {
+        OwnedFastqRecords(self)
+    }
+}
+
+#[gat]
+impl<B: FillBuffer> LendingIterator for FastqRecords<B> {
+    type Item<'next>
+    where
+        Self: 'next,
+    = Result<RefFastqRecord<'next>, BioReaderError>;
+
+    fn next(&mut self) -> Option<Item<'_, Self>> {
+        self.next_record().transpose()
+    }
+}
+
+pub struct OwnedFastqRecords<B>(FastqRecords<B>);
+
+impl<B: FillBuffer> Iterator for OwnedFastqRecords<B> {
+    type Item = Result<OwnedFastqRecord, BioReaderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_record().transpose().map(|record| record.map(|record| record.to_owned()))
+    }
+}
+
+#[gat]
+impl<T: Read> LendingIterator for PairedFastqReader<T> {
+    type Item<'next>
+    where
+        Self: 'next,
+    = Result<(RefFastqRecord<'next>, RefFastqRecord<'next>), BioReaderError>;
+
+    /// Stops after the first error
+    fn next(&mut self) -> Option<Item<'_, Self>> {
+        self.next_pair().transpose()
+    }
+}
+
+impl<T: Read> PairedFastqReader<T> {
+    /// Iterator over copies of the pairs
+    pub fn owned(self) -> OwnedFastqPairs<T> {
+        OwnedFastqPairs(self)
+    }
+}
+
+pub struct OwnedFastqPairs<T: Read>(PairedFastqReader<T>);
+
+impl<T: Read> Iterator for OwnedFastqPairs<T> {
+    type Item = Result<(OwnedFastqRecord, OwnedFastqRecord), BioReaderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pair = LendingIterator::next(&mut self.0)?;
+        Some(pair.map(|(record1, record2)| (record1.to_owned(), record2.to_owned())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::fastq_byte_reader::FastqByteReader;
+
+    const RECORD: &str = "@read\nACGT\n+\nIIII\n";
+
+    fn first_error(input: &str, chunk_size: usize) -> BioReaderError {
+        let mut byte_reader = FastqByteReader::new(Cursor::new(input.as_bytes().to_vec()), chunk_size).unwrap();
+        let mut reader = FastqReader::with_capacity(chunk_size);
+        loop {
+            match reader.load_batch(&mut byte_reader) {
+                Ok(Some(())) => {}
+                Ok(None) => panic!("Expected an error"),
+                Err(err) => return err,
+            }
+            loop {
+                match reader.next() {
+                    Ok(Some(_)) => {}
+                    Ok(None) => break,
+                    Err(err) => return err,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        let position = RecordPosition {
+            byte_offset: 3 * RECORD.len() as u64,
+            record_number: 4,
+        };
+        let prefix = RECORD.repeat(3);
+
+        for chunk_size in [20, 1 << 10] {
+            let err = first_error(&format!("{prefix}@read\nACGT\n+\nII"), chunk_size);
+            assert!(matches!(err, BioReaderError::TruncatedRecord { position: p } if p == position), "{err}");
+
+            let err = first_error(&format!("{prefix}@read\nACGT\n"), chunk_size);
+            assert!(matches!(err, BioReaderError::TruncatedRecord { position: p } if p == position), "{err}");
+
+            let err = first_error(&format!("{prefix}@read\nACGT\n-\nIIII\n{RECORD}"), chunk_size);
+            assert!(matches!(err, BioReaderError::MissingSeparator { position: p } if p == position), "{err}");
+
+            let err = first_error(&format!("{prefix}@read\nACGT\n+\nIII\n{RECORD}"), chunk_size);
+            assert!(
+                matches!(err, BioReaderError::LengthMismatch { sequence: 4, quality: 3, position: p } if p == position),
+                "{err}"
+            );
+        }
+
+        let err = first_error(&format!("x{RECORD}"), 1 << 10);
+        assert!(matches!(err, BioReaderError::InvalidByte { byte: b'x', .. }), "{err}");
+    }
+
+    #[test]
+    fn test_unpaired_reads() {
+        let byte_reader = FastqPairedByteReader::new(
+            Cursor::new(RECORD.repeat(3).into_bytes()),
+            Cursor::new(RECORD.repeat(2).into_bytes()),
+            1 << 10,
+        );
+        let mut reader = PairedFastqReader::new(Arc::new(Mutex::new(byte_reader)), 1 << 10);
+        let mut result = reader.next_pair().map(|pair| pair.is_some());
+        while let Ok(true) = result {
+            result = reader.next_pair().map(|pair| pair.is_some());
+        }
+        assert!(matches!(result, Err(BioReaderError::UnpairedReads { .. })));
+        // Nothing is returned after the error
+        assert!(matches!(reader.next_pair(), Ok(None)));
+    }
+
+    #[test]
+    fn test_iterators_stop_after_error() {
+        let input = format!("{RECORD}@read2\nACGT\n-\nIIII\n{RECORD}{RECORD}");
+        let byte_reader = || FastqByteReader::new(Cursor::new(input.clone().into_bytes()), 1 << 10).unwrap();
+
+        let results: Vec<_> = FastqReader::with_capacity(1 << 10).records(byte_reader()).owned().collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(BioReaderError::MissingSeparator { .. })));
+
+        let mut records = FastqReader::with_capacity(1 << 10).records(byte_reader());
+        assert!(records.next().unwrap().is_ok());
+        assert!(records.next().unwrap().is_err());
+        for _ in 0..10 {
+            assert!(records.next().is_none());
+        }
+
+        let byte_reader = FastqPairedByteReader::new(Cursor::new(input.clone().into_bytes()), Cursor::new(input.into_bytes()), 1 << 10);
+        let pairs: Vec<_> = PairedFastqReader::new(Arc::new(Mutex::new(byte_reader)), 1 << 10).owned().collect();
+        assert_eq!(pairs.len(), 2);
+        assert!(pairs[1].is_err());
+    }
+
+    #[test]
+    fn test_from_path() {
+        let plain = FastqReader::from_path("data/fastq/small_test_1.fq").unwrap().owned().collect::<Result<Vec<_>, _>>().unwrap();
+        let gzip = FastqReader::from_path("data/fastq/small_test_gzip_1.fq.gz").unwrap().owned().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(plain.len(), 10000);
+        assert!(plain.iter().zip(&gzip).all(|(record1, record2)| record1.seq() == record2.seq()));
+        assert_eq!(plain.len(), gzip.len());
+    }
+
+    #[test]
+    fn test_iterators() {
+        let input = (0..50).map(|i| format!("@read{i}\nACGT\n+\nIIII\n")).collect::<String>();
+        let byte_reader = || FastqByteReader::new(Cursor::new(input.clone().into_bytes()), 64).unwrap();
+
+        let mut records = FastqReader::with_capacity(64).records(byte_reader());
+        let mut count = 0;
+        while let Some(record) = records.next() {
+            assert_eq!(record.unwrap().head(), format!("read{count}").as_bytes());
+            count += 1;
+        }
+        assert_eq!(count, 50);
+
+        let owned = FastqReader::with_capacity(64).records(byte_reader()).owned().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(owned.len(), 50);
+        assert_eq!((owned[49].head(), owned[49].seq()), (b"read49".as_slice(), b"ACGT".as_slice()));
+
+        let byte_reader = FastqPairedByteReader::new(Cursor::new(input.clone().into_bytes()), Cursor::new(input.clone().into_bytes()), 64);
+        let pairs = PairedFastqReader::new(Arc::new(Mutex::new(byte_reader)), 64).owned().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(pairs.len(), 50);
+        assert!(pairs.iter().all(|(record1, record2)| record1.head() == record2.head()));
+    }
+}
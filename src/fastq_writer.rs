@@ -0,0 +1,175 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Error, Write},
+    path::Path,
+};
+
+use crate::{
+    compression::{Compression, Encoder},
+    sequence::fastq_record::{FastqRecord, LineEnding},
+};
+
+const DEFAULT_CAPACITY: usize = 1 << 20;
+
+/// Append a FASTQ record to a byte buffer.
+///
+/// Worker threads can format their records into a local buffer and hand the
+/// whole chunk to [`FastqWriter::write_chunk`], so the writer only has to be
+/// locked once per chunk.
+#[inline]
+pub fn format_record<R: FastqRecord>(record: &R, line_ending: LineEnding, out: &mut Vec<u8>) {
+    let eol: &[u8] = match line_ending {
+        LineEnding::Unix => b"\n",
+        LineEnding::Windows => b"\r\n",
+    };
+    out.push(b'@');
+    out.extend_from_slice(record.head());
+    out.extend_from_slice(eol);
+    out.extend_from_slice(record.seq());
+    out.extend_from_slice(eol);
+    out.push(b'+');
+    out.extend_from_slice(record.sep());
+    out.extend_from_slice(eol);
+    out.extend_from_slice(record.qual());
+    out.extend_from_slice(eol);
+}
+
+/// Buffered FASTQ writer with optional compression.
+///
+/// Unless set explicitly, the line ending is taken from the first record that
+/// knows which line ending its input used and falls back to Unix otherwise.
+pub struct FastqWriter<W: Write> {
+    writer: BufWriter<Encoder<W>>,
+    line_ending: Option<LineEnding>,
+    record: Vec<u8>,
+}
+
+impl FastqWriter<File> {
+    /// Create a file, compressed according to its extension (`.gz`, `.bgz`, `.zst`)
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let compression = Compression::from_path(&path);
+        Self::with_compression(File::create(path)?, compression)
+    }
+}
+
+impl<W: Write> FastqWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_compression(writer, Compression::None).expect("Uncompressed writer cannot fail")
+    }
+
+    pub fn with_compression(writer: W, compression: Compression) -> Result<Self, Error> {
+        Ok(Self {
+            writer: BufWriter::with_capacity(DEFAULT_CAPACITY, compression.encoder(writer)?),
+            line_ending: None,
+            record: Vec::new(),
+        })
+    }
+
+    pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = Some(line_ending);
+        self
+    }
+
+    #[inline]
+    pub fn line_ending(&self) -> Option<LineEnding> {
+        self.line_ending
+    }
+
+    #[inline]
+    pub fn write<R: FastqRecord>(&mut self, record: &R) -> Result<(), Error> {
+        let line_ending = *self
+            .line_ending
+            .get_or_insert_with(|| record.line_ending().unwrap_or(LineEnding::Unix));
+
+        self.record.clear();
+        format_record(record, line_ending, &mut self.record);
+        self.writer.write_all(&self.record)
+    }
+
+    /// Write records that were already formatted with [`format_record`]
+    #[inline]
+    pub fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        self.writer.write_all(chunk)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+
+    /// Flush all buffered records, finish the compressed stream and return the underlying writer
+    pub fn finish(self) -> Result<W, Error> {
+        self.writer.into_inner().map_err(|err| err.into_error())?.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Read};
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::{fastq_byte_reader::FastqByteReader, fastq_reader::FastqReader, sequence::fastq_record::OwnedFastqRecord};
+
+    fn roundtrip(compression: Compression) -> (Vec<u8>, Vec<u8>) {
+        let path = "data/fastq/small_test_1.fq";
+        let mut original = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut original).unwrap();
+
+        let mut byte_reader = FastqByteReader::new(File::open(path).unwrap(), 1 << 16).unwrap();
+        let mut reader = FastqReader::with_capacity(1 << 16);
+        let mut writer = FastqWriter::with_compression(Vec::new(), compression).unwrap();
+        while let Some(()) = reader.load_batch(&mut byte_reader).unwrap() {
+            while let Some(record) = reader.next().unwrap() {
+                writer.write(&record).unwrap();
+            }
+        }
+        (original, writer.finish().unwrap())
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (original, written) = roundtrip(Compression::None);
+        assert_eq!(original, written);
+    }
+
+    #[test]
+    fn test_roundtrip_gzip() {
+        let (original, written) = roundtrip(Compression::Gzip);
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&written[..]).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(original, decompressed);
+    }
+
+    #[test]
+    fn test_roundtrip_bgzf() {
+        let (original, written) = roundtrip(Compression::from_path("reads.fq.bgz"));
+        assert!(crate::bgzf::is_bgzf(&written));
+        let mut decompressed = Vec::new();
+        crate::bgzf::BgzfReader::new(&written[..]).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(original, decompressed);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_roundtrip_zstd() {
+        let (original, written) = roundtrip(Compression::Zstd);
+        assert_eq!(Compression::detect(&written), Compression::Zstd);
+        let (_, mut reader) = crate::compression::decode(&written[..]).unwrap();
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(original, decompressed);
+    }
+
+    #[test]
+    fn test_line_ending() {
+        let record = OwnedFastqRecord {
+            header: b"read0 comment".to_vec(),
+            sequence: b"ACGT".to_vec(),
+            quality: b"IIII".to_vec(),
+        };
+        let mut writer = FastqWriter::new(Vec::new()).with_line_ending(LineEnding::Windows);
+        writer.write(&record).unwrap();
+        assert_eq!(writer.finish().unwrap(), b"@read0 comment\r\nACGT\r\n+\r\nIIII\r\n");
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    fs::File,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use crate::{
+    bam_reader::BamReader,
+    compression::{decode_par, peek, Compression, DynRead},
+    error::BioReaderError,
+    fasta_byte_reader::FastaByteReader,
+    fasta_reader::FastaReader,
+    fastq_byte_reader::FastqByteReader,
+    fastq_reader::FastqReader,
+    sam_byte_reader::SamByteReader,
+    sam_reader::SamReader,
+};
+
+pub const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+
+// Decompressed bytes inspected to detect the format
+const PEEK_LEN: usize = 1024;
+const SAM_HEADER_KINDS: [&[u8; 2]; 5] = [b"HD", b"SQ", b"RG", b"PG", b"CO"];
+
+/// File format of decompressed input
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Fastq,
+    Fasta,
+    Sam,
+    Bam,
+}
+
+impl Format {
+    /// Detect the format from the first bytes of decompressed data
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        match bytes.first()? {
+            b'>' => Some(Self::Fasta),
+            // SAM header lines start with a two letter record type followed by a tab
+            b'@' if bytes.len() > 3 && bytes[3] == b'\t' && SAM_HEADER_KINDS.iter().any(|kind| bytes[1..3] == kind[..]) => {
+                Some(Self::Sam)
+            }
+            b'@' => Some(Self::Fastq),
+            _ if bytes.starts_with(b"BAM\x01") => Some(Self::Bam),
+            // SAM without header, the first line must hold tab separated fields
+            _ => {
+                let line_end = memchr::memchr(b'\n', bytes).unwrap_or(bytes.len());
+                memchr::memchr(b'\t', &bytes[..line_end]).map(|_| Self::Sam)
+            }
+        }
+    }
+}
+
+/// Byte reader and record reader for a detected input format.
+///
+/// BAM records are decoded by [`BamReader`] directly, so it has no separate
+/// byte reader.
+// There is one reader per input, boxing the variants would only add an indirection
+#[allow(clippy::large_enum_variant)]
+pub enum InputReader {
+    Fastq {
+        byte_reader: FastqByteReader<DynRead<'static>>,
+        reader: FastqReader,
+    },
+    Fasta {
+        byte_reader: FastaByteReader<DynRead<'static>>,
+        reader: FastaReader,
+    },
+    Sam {
+        byte_reader: SamByteReader<DynRead<'static>>,
+        reader: SamReader,
+    },
+    Bam(BamReader<DynRead<'static>>),
+}
+
+impl InputReader {
+    /// Detect compression and format of a reader
+    pub fn new<R: Read + Send + 'static>(reader: R, chunk_size: usize) -> Result<Self, BioReaderError> {
+        Self::with_threads(reader, chunk_size, 1)
+    }
+
+    /// Decompress the input on `num_threads` threads, see [`Compression::decoder_par`]
+    pub fn with_threads<R: Read + Send + 'static>(reader: R, chunk_size: usize, num_threads: usize) -> Result<Self, BioReaderError> {
+        let (compression, mut reader) = decode_par(reader, num_threads)?;
+        let head = peek(&mut reader, PEEK_LEN)?;
+        let format = Format::detect(&head).ok_or(BioReaderError::UnknownFormat)?;
+        let reader: DynRead<'static> = Box::new(Cursor::new(head).chain(reader));
+
+        Ok(match format {
+            Format::Fastq => Self::Fastq {
+                byte_reader: FastqByteReader::new(reader, chunk_size)?,
+                reader: FastqReader::with_capacity(chunk_size),
+            },
+            Format::Fasta => Self::Fasta {
+                byte_reader: FastaByteReader::new(reader, chunk_size)?,
+                reader: FastaReader::with_capacity(chunk_size),
+            },
+            Format::Sam => Self::Sam {
+                byte_reader: SamByteReader::new(reader, chunk_size)?,
+                reader: SamReader::with_capacity(chunk_size),
+            },
+            Format::Bam => {
+                // The decoder would otherwise treat BGZF as plain gzip
+                if compression != Compression::Bgzf {
+                    return Err(BioReaderError::BamNotBgzf { compression });
+                }
+                Self::Bam(BamReader::from_decompressed(reader)?)
+            }
+        })
+    }
+
+    pub fn format(&self) -> Format {
+        match self {
+            Self::Fastq { .. } => Format::Fastq,
+            Self::Fasta { .. } => Format::Fasta,
+            Self::Sam { .. } => Format::Sam,
+            Self::Bam(_) => Format::Bam,
+        }
+    }
+}
+
+/// Open a FASTQ, FASTA, SAM or BAM file with any supported compression
+pub fn open_reader(path: impl AsRef<Path>) -> Result<InputReader, BioReaderError> {
+    InputReader::new(File::open(path)?, DEFAULT_CHUNK_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+
+    use super::*;
+    use crate::bgzf::tests::bgzf;
+
+    const FASTQ: &[u8] = b"@read1\nACGT\n+\nIIII\n@read2\nTTGA\n+\n@@@@\n";
+
+    #[test]
+    fn test_detect_compression() {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(FASTQ).unwrap();
+        let gzip = encoder.finish().unwrap();
+
+        assert_eq!(Compression::detect(&gzip), Compression::Gzip);
+        assert_eq!(Compression::detect(&bgzf(FASTQ)), Compression::Bgzf);
+        assert_eq!(Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0]), Compression::Zstd);
+        assert_eq!(Compression::detect(b"BZh91AY&SY"), Compression::Bzip2);
+        assert_eq!(Compression::detect(b"\xfd7zXZ\0\0"), Compression::Xz);
+        assert_eq!(Compression::detect(FASTQ), Compression::None);
+
+        for (input, threads) in [(FASTQ.to_vec(), 1), (gzip.clone(), 1), (gzip, 2), (bgzf(FASTQ), 1), (bgzf(FASTQ), 3)] {
+            let input = InputReader::with_threads(Cursor::new(input), 64, threads).unwrap();
+            let InputReader::Fastq { mut byte_reader, mut reader } = input else {
+                panic!("Expected FASTQ input");
+            };
+            let mut count = 0;
+            while let Some(()) = reader.load_batch(&mut byte_reader).unwrap() {
+                while reader.next().unwrap().is_some() {
+                    count += 1;
+                }
+            }
+            assert_eq!(count, 2);
+        }
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(Format::detect(b">chr1\nACGT\n"), Some(Format::Fasta));
+        assert_eq!(Format::detect(b"@HD\tVN:1.6\n"), Some(Format::Sam));
+        assert_eq!(Format::detect(b"@HDread\nACGT\n+\nIIII\n"), Some(Format::Fastq));
+        assert_eq!(Format::detect(b"r1\t4\t*\t0\t0\t*\t*\t0\t0\tA\tI\n"), Some(Format::Sam));
+        assert_eq!(Format::detect(b"BAM\x01"), Some(Format::Bam));
+        assert_eq!(Format::detect(b"ACGT\n"), None);
+        assert_eq!(Format::detect(b""), None);
+
+        if !cfg!(feature = "xz") {
+            let input = InputReader::new(Cursor::new(b"\xfd7zXZ\0\0\0".to_vec()), 64);
+            assert!(matches!(input, Err(BioReaderError::Io(err)) if err.kind() == std::io::ErrorKind::Unsupported));
+        }
+
+        let input = InputReader::new(Cursor::new(b"ACGT\n".to_vec()), 64);
+        assert!(matches!(input, Err(BioReaderError::UnknownFormat)));
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"BAM\x01").unwrap();
+        let input = InputReader::new(Cursor::new(encoder.finish().unwrap()), 64);
+        assert!(matches!(input, Err(BioReaderError::BamNotBgzf { compression: Compression::Gzip })));
+    }
+
+    #[cfg(all(feature = "bzip2", feature = "xz"))]
+    #[test]
+    fn test_bzip2_xz() {
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(FASTQ).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(FASTQ).unwrap();
+
+        for (compressed, expected) in [(bzip2.finish().unwrap(), Compression::Bzip2), (xz.finish().unwrap(), Compression::Xz)] {
+            let (compression, mut reader) = crate::compression::decode(&compressed[..]).unwrap();
+            assert_eq!(compression, expected);
+            let mut decompressed = Vec::new();
+            reader.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, FASTQ);
+        }
+    }
+}
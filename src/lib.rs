@@ -1,10 +1,21 @@
-#![feature(slice_internals)]
-
 pub mod sequence;
+pub mod error;
+pub mod validation;
 pub mod fastq_byte_reader;
 pub mod fasta_byte_reader;
 pub mod fastq_reader;
 pub mod fasta_reader;
+pub mod fastq_writer;
+pub mod fasta_writer;
+pub mod sam_header;
+pub mod sam_byte_reader;
+pub mod sam_reader;
+pub mod sam_writer;
+pub mod bgzf;
+pub mod read_ahead;
+pub mod bam_reader;
+pub mod compression;
+pub mod format;
 mod reader_utils;
 pub mod parallel;
 pub mod utils;
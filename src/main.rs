@@ -1,19 +1,21 @@
-#![feature(slice_internals)]
-use core::slice::memchr::memchr;
+// Scratch benchmarks, toggled on and off from main()
+#![allow(dead_code)]
+
+use memchr::memchr;
 use std::{
     fs::File,
     io::{Error, Read},
     path::Path,
-    sync::{Arc, Mutex},
 };
 
-use bioreader::{fasta_byte_reader::FastaByteReader, parallel::fastq::read_fasta_par, utils};
+use bioreader::{parallel::fastq::read_fasta_par, utils};
 use bioreader::{
     fastq_byte_reader::FastqByteReader,
     fasta_reader::FastaReader,
     fastq_reader::FastqReader,
     parallel::fastq::read_fastq_pair_par,
     sequence::{fasta_record::OwnedFastaRecord, fastq_record::RefFastqRecord},
+    validation::ValidationPolicy,
 };
 use flate2::read::GzDecoder;
 use memmap2::Mmap;
@@ -22,18 +24,16 @@ fn read_fastq<T>(file: T, buffer_size: usize) -> Result<usize, std::io::Error>
 where
     T: Read,
 {
-    let mut byte_reader = FastqByteReader::new(file, buffer_size)?;
+    let byte_reader = FastqByteReader::new(file, buffer_size)?;
     // let mut byte_reader = BufReader::with_capacity(buffer_size, file);
-    let mut fastq_reader = FastqReader::with_capacity(buffer_size);
+    let mut records = FastqReader::with_capacity(buffer_size).records(byte_reader);
 
     let mut count = 0;
     let mut total_length = 0;
-    while let Some(_ok) = fastq_reader.load_batch(&mut byte_reader)? {
-        while let Some(record) = fastq_reader.next() {
-            // println!("{}", record);
-            count += 1;
-            total_length += record.seq().len();
-        }
+    while let Some(record) = records.next_record()? {
+        // println!("{}", record);
+        count += 1;
+        total_length += record.seq().len();
     }
     println!("Count: {count}");
 
@@ -61,34 +61,17 @@ fn read_fastq_mm(path: impl AsRef<Path>) -> Result<usize, std::io::Error> {
 fn test_fa() -> Result<(), Error> {
     let path: &Path = Path::new("data/fasta/test.fna");
 
-    let buffer_size = 1_000_000;
-    let file: File = match File::open(&path) {
-        Err(why) => panic!("couldn't open {}: {}", path.display(), why),
-        Ok(file) => file,
-    };
-
-    let mut byte_reader = Arc::new(Mutex::new(FastaByteReader::new(file, buffer_size)?));
-    let mut fasta_reader = FastaReader::with_capacity(buffer_size);
+    let mut records = FastaReader::from_path(path)?;
 
     let mut count = 0;
     let mut total_length = 0;
 
-    let mut record = OwnedFastaRecord::new();
-
-    while let Some(()) = fasta_reader
-        .load_batch_par(&mut byte_reader)
-        .expect("Batch is invalid")
-    {
-        while let Some(_) = fasta_reader.next(&mut record) {
-            count += 1;
-            total_length += record.seq().len();
-            // println!("{}", record.to_string());
-        }
-        // println!("{}", String::from_utf8_lossy(record.head()));
+    while let Some(record) = records.next_record()? {
+        count += 1;
+        total_length += record.seq_len();
     }
-    
 
-    println!("Count: {count}");
+    println!("Count: {count}, Length: {total_length}");
 
     Ok(())
 }
@@ -111,8 +94,8 @@ fn test_paired_fq_parallel() -> Result<(), Error> {
     let path_1: &Path = Path::new("data/large_data/fastq/weird_1.fq.gz");
     let path_2: &Path = Path::new("data/large_data/fastq/weird_2.fq.gz");
 
-    let file_1 = File::open(&path_1)?;
-    let file_2 = File::open(&path_2)?;
+    let file_1 = File::open(path_1)?;
+    let file_2 = File::open(path_2)?;
 
     let (duration, result) = utils::time(move || {
         read_fastq_pair_par(
@@ -120,6 +103,7 @@ fn test_paired_fq_parallel() -> Result<(), Error> {
             GzDecoder::new(file_2),
             usize::pow(2, 24),
             8,
+            ValidationPolicy::default(),
             worker,
         )
     })
@@ -144,16 +128,17 @@ fn test_fa_parallel() -> Result<(), Error> {
         kmer_count as usize
     };
 
-    let path: &Path = Path::new("/usr/users/QIB_fr017/fritsche/ProjectsPrivate/bioreader/data/fasta/test.fna");
+    // let path: &Path = Path::new("/usr/users/QIB_fr017/fritsche/ProjectsPrivate/bioreader/data/fasta/test.fna");
     let path: &Path = Path::new("/usr/users/QIB_fr017/fritsche/ProjectsPrivate/flexalign/data/large_data/fasta/combined.fna");
 
-    let file = File::open(&path)?;
+    let file = File::open(path)?;
 
     let (duration, result) = utils::time(move || {
         read_fasta_par(
             file,
             usize::pow(2, 24),
             4,
+            ValidationPolicy::default(),
             worker,
         )
     })
@@ -0,0 +1,1142 @@
+use std::{
+    io::Read,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, SyncSender},
+        Arc,
+    },
+    thread::{Scope, ScopedJoinHandle},
+    time::Duration,
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+
+use crate::{
+    compression::{decode, decode_par, Compression, DynRead},
+    error::BioReaderError,
+    fasta_byte_reader::FastaByteReader,
+    fasta_reader::FastaReader,
+    fastq_byte_reader::{FastqByteReader, FastqPairedByteReader},
+    fastq_reader::{FastqReader, PairedFastqReader},
+    format::DEFAULT_CHUNK_SIZE,
+    read_ahead::ReadAhead,
+    parallel::{
+        fastq::Merge,
+        producer::{produce, produce_pair, BufferPool},
+        progress::{CountingReader, Progress, ProgressCallback, ProgressCounters},
+        reorder::ReorderBuffer,
+    },
+    sequence::{fasta_record::RefFastaRecord, fastq_record::RefFastqRecord},
+    validation::ValidationPolicy,
+};
+
+/// Error returned by a fallible worker closure
+pub type WorkerError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Stops a run from any thread, e.g. from within a worker closure once enough records were seen.
+///
+/// Workers finish the record at hand and no further chunks are read. The run
+/// returns the results of all records processed until then.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counters and cancellation shared by the workers of one run
+struct Tracker<'a> {
+    callback: Option<&'a ProgressCallback>,
+    token: Option<&'a CancellationToken>,
+    counters: Arc<ProgressCounters>,
+    decompression_threads: usize,
+    window: Option<&'a Receiver<()>>,
+    // Set when a worker fails
+    cancelled: AtomicBool,
+}
+
+impl<'a> Tracker<'a> {
+    fn new(builder: &'a ParallelReaderBuilder) -> Self {
+        Self {
+            callback: builder.progress.as_ref(),
+            token: builder.cancellation.as_ref(),
+            counters: builder.counters.clone().unwrap_or_default(),
+            decompression_threads: builder.decompression_threads,
+            window: builder.window.as_ref(),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.token.is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Wait until the producer may read another chunk, false if the run stops meanwhile
+    fn wait_for_window(&self) -> bool {
+        let Some(permits) = self.window else {
+            return true;
+        };
+        loop {
+            // A failed worker never sends its chunk, so no permit might come back
+            match permits.recv_timeout(Duration::from_millis(10)) {
+                Ok(()) => return true,
+                Err(RecvTimeoutError::Timeout) if !self.is_cancelled() => {}
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Run a worker, the other workers stop after their current chunk if it fails
+    fn cancel_on_error<S>(&self, work: impl FnOnce() -> Result<S, BioReaderError>) -> Result<S, BioReaderError> {
+        let result = work();
+        if result.is_err() {
+            self.cancelled.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Count the raw input bytes read through `reader` and the bytes it decompresses to
+    fn decoder<'scope, R: Read + Send + 'scope>(
+        &'scope self,
+        scope: &'scope Scope<'scope, '_>,
+        compression: Option<Compression>,
+        reader: R,
+    ) -> Result<DynRead<'scope>, BioReaderError> {
+        let reader = CountingReader::new(reader, &self.counters.bytes_read);
+        let threads = self.decompression_threads;
+        let decoded = if threads > 1 {
+            // The decompression threads need a 'static reader, the input is read on a thread of the scope
+            let reader = ReadAhead::scoped(scope, reader);
+            match compression {
+                Some(compression) => compression.decoder_par(reader, threads)?,
+                None => decode_par(reader, threads)?.1,
+            }
+        } else {
+            match compression {
+                Some(compression) => compression.decoder(reader)?,
+                None => decode(reader)?.1,
+            }
+        };
+        Ok(Box::new(CountingReader::new(decoded, &self.counters.bytes_decompressed)))
+    }
+
+    fn chunk_loaded(&self) {
+        self.counters.chunks_in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn chunk_done(&self, records: u64) {
+        self.counters.records.fetch_add(records, Ordering::Relaxed);
+        self.counters.chunks.fetch_add(1, Ordering::Relaxed);
+        self.counters.chunks_in_flight.fetch_sub(1, Ordering::Relaxed);
+        if let Some(callback) = self.callback {
+            callback(self.counters.snapshot());
+        }
+    }
+}
+
+/// Run the records of a chunk, a panic is returned as its message
+fn catch_panic<T>(work: impl FnOnce() -> Result<T, BioReaderError>) -> Result<Result<T, BioReaderError>, String> {
+    std::panic::catch_unwind(AssertUnwindSafe(work)).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "Unknown panic".to_string())
+    })
+}
+
+/// Wait for the producer and all workers and return the results of the workers or the first error
+fn join_workers<T>(
+    producer: ScopedJoinHandle<'_, Result<(), BioReaderError>>,
+    threads: Vec<ScopedJoinHandle<'_, Result<T, BioReaderError>>>,
+) -> Result<Vec<T>, BioReaderError> {
+    let mut results = Vec::with_capacity(threads.len());
+    // Workers end without an error if the producer fails
+    let mut error = producer.join().expect("Producer thread panicked").err();
+    for thread_guard in threads {
+        match thread_guard.join().expect("Worker thread panicked") {
+            Ok(result) => results.push(result),
+            Err(err) => {
+                error.get_or_insert(err);
+            }
+        }
+    }
+    match error {
+        Some(err) => Err(err),
+        None => Ok(results),
+    }
+}
+
+/// Configuration of a parallel run over FASTQ or FASTA input.
+///
+/// Every worker thread folds the records it reads into its own state, the
+/// states of all workers are merged at the end.
+///
+/// ```no_run
+/// use bioreader::{parallel::builder::ParallelReaderBuilder, validation::{Validation, ValidationPolicy}};
+///
+/// let file = std::fs::File::open("reads.fq.gz").unwrap();
+/// let bases: usize = ParallelReaderBuilder::new()
+///     .num_threads(8)
+///     .validation(ValidationPolicy { validation: Validation::Dna, ..Default::default() })
+///     .run_fastq(file, |record, bases: &mut usize| *bases += record.seq().len())
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct ParallelReaderBuilder {
+    num_threads: usize,
+    chunk_size: usize,
+    validation: ValidationPolicy,
+    max_record_length: Option<usize>,
+    multiline: bool,
+    compression: Option<Compression>,
+    decompression_threads: usize,
+    ordered: bool,
+    progress: Option<ProgressCallback>,
+    counters: Option<Arc<ProgressCounters>>,
+    cancellation: Option<CancellationToken>,
+    // Permits for the producer of an ordered `map_*` run, one per chunk read ahead of the next chunk to emit
+    window: Option<Receiver<()>>,
+}
+
+impl Default for ParallelReaderBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParallelReaderBuilder {
+    /// One thread per core, [`DEFAULT_CHUNK_SIZE`] chunks, IUPAC validation and detected compression
+    pub fn new() -> Self {
+        Self {
+            num_threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            validation: ValidationPolicy::default(),
+            max_record_length: None,
+            multiline: false,
+            compression: None,
+            decompression_threads: 1,
+            ordered: false,
+            progress: None,
+            counters: None,
+            cancellation: None,
+            window: None,
+        }
+    }
+
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = std::cmp::max(num_threads, 1);
+        self
+    }
+
+    /// Bytes per chunk handed to a worker, at least 1
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = std::cmp::max(chunk_size, 1);
+        self
+    }
+
+    pub fn validation(mut self, validation: ValidationPolicy) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Fail with [`BioReaderError::RecordTooLarge`] if a chunk would need to grow beyond this many bytes to hold a record
+    pub fn max_record_length(mut self, max_record_length: usize) -> Self {
+        self.max_record_length = Some(max_record_length);
+        self
+    }
+
+    /// Accept FASTQ records with wrapped sequence and quality lines, see [`FastqByteReader::with_multiline`]
+    pub fn multiline_fastq(mut self, multiline: bool) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Decompress the input as given instead of detecting the compression from its magic bytes
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Decompress the input on `num_threads` threads besides the workers, see [`Compression::decoder_par`].
+    ///
+    /// With 1, the default, the input is decompressed on the thread filling the chunks.
+    pub fn decompression_threads(mut self, num_threads: usize) -> Self {
+        self.decompression_threads = std::cmp::max(num_threads, 1);
+        self
+    }
+
+    /// Emit the results of `map_*` runs in input order instead of as soon as a chunk is done
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Call `callback` with the totals of the run after every chunk
+    pub fn progress(mut self, callback: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Count into `counters`, e.g. to poll [`ProgressCounters::snapshot`] from another thread during the run
+    pub fn progress_counters(mut self, counters: Arc<ProgressCounters>) -> Self {
+        self.counters = Some(counters);
+        self
+    }
+
+    /// Stop the run once `token` is cancelled
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Fold single-end FASTQ records into a state per worker and merge them
+    pub fn run_fastq<R, S, F>(&self, reader: R, f: F) -> Result<S, BioReaderError>
+    where
+        R: Read + Send,
+        S: Default + Send + Merge,
+        F: FnMut(&RefFastqRecord, &mut S) + Clone + Send,
+    {
+        Ok(merge_all(self.fastq_states(reader, S::default, f)?))
+    }
+
+    /// Fold pairs of FASTQ records into a state per worker and merge them
+    pub fn run_fastq_pair<R1, R2, S, F>(&self, reader1: R1, reader2: R2, f: F) -> Result<S, BioReaderError>
+    where
+        R1: Read + Send,
+        R2: Read + Send,
+        S: Default + Send + Merge,
+        F: FnMut(&RefFastqRecord, &RefFastqRecord, &mut S) + Clone + Send,
+    {
+        Ok(merge_all(self.fastq_pair_states(reader1, reader2, S::default, f)?))
+    }
+
+    /// Fold FASTA records into a state per worker and merge them.
+    ///
+    /// Records borrow the chunk, their sequence is read line by line with
+    /// [`RefFastaRecord::seq_lines`] or copied with [`RefFastaRecord::to_owned`].
+    pub fn run_fasta<R, S, F>(&self, reader: R, f: F) -> Result<S, BioReaderError>
+    where
+        R: Read + Send,
+        S: Default + Send + Merge,
+        F: FnMut(&RefFastaRecord, &mut S) + Clone + Send,
+    {
+        Ok(merge_all(self.fasta_states(reader, S::default, f)?))
+    }
+
+    /// Like [`ParallelReaderBuilder::run_fastq`], an error returned by `f` stops all workers.
+    ///
+    /// The error is returned as [`BioReaderError::WorkerFailed`] with the failing chunk and record.
+    pub fn try_run_fastq<R, S, F, E>(&self, reader: R, mut f: F) -> Result<S, BioReaderError>
+    where
+        R: Read + Send,
+        S: Default + Send + Merge,
+        F: FnMut(&RefFastqRecord, &mut S) -> Result<(), E> + Clone + Send,
+        E: Into<WorkerError>,
+    {
+        let f = move |record: &RefFastqRecord, state: &mut S| f(record, state).map_err(Into::into);
+        Ok(merge_all(self.fastq_chunks(reader, S::default, f, |_, _| true)?))
+    }
+
+    /// Like [`ParallelReaderBuilder::run_fastq_pair`], an error returned by `f` stops all workers
+    pub fn try_run_fastq_pair<R1, R2, S, F, E>(&self, reader1: R1, reader2: R2, mut f: F) -> Result<S, BioReaderError>
+    where
+        R1: Read + Send,
+        R2: Read + Send,
+        S: Default + Send + Merge,
+        F: FnMut(&RefFastqRecord, &RefFastqRecord, &mut S) -> Result<(), E> + Clone + Send,
+        E: Into<WorkerError>,
+    {
+        let f = move |record1: &RefFastqRecord, record2: &RefFastqRecord, state: &mut S| f(record1, record2, state).map_err(Into::into);
+        Ok(merge_all(self.fastq_pair_chunks(reader1, reader2, S::default, f, |_, _| true)?))
+    }
+
+    /// Like [`ParallelReaderBuilder::run_fasta`], an error returned by `f` stops all workers
+    pub fn try_run_fasta<R, S, F, E>(&self, reader: R, mut f: F) -> Result<S, BioReaderError>
+    where
+        R: Read + Send,
+        S: Default + Send + Merge,
+        F: FnMut(&RefFastaRecord, &mut S) -> Result<(), E> + Clone + Send,
+        E: Into<WorkerError>,
+    {
+        let f = move |record: &RefFastaRecord, state: &mut S| f(record, state).map_err(Into::into);
+        Ok(merge_all(self.fasta_chunks(reader, S::default, f, |_, _| true)?))
+    }
+
+    /// Combine the results of `f` for all single-end FASTQ records with `reduce`, `None` without records
+    pub fn reduce_fastq<R, O, F, Red>(&self, reader: R, mut f: F, reduce: Red) -> Result<Option<O>, BioReaderError>
+    where
+        R: Read + Send,
+        O: Send,
+        F: FnMut(&RefFastqRecord) -> O + Clone + Send,
+        Red: Fn(O, O) -> O + Clone + Send,
+    {
+        let reduce_local = reduce.clone();
+        let states = self.fastq_states(reader, || None, move |record, acc: &mut Option<O>| {
+            reduce_into(&reduce_local, acc, f(record))
+        })?;
+        Ok(states.into_iter().flatten().reduce(reduce))
+    }
+
+    /// Combine the results of `f` for all pairs of FASTQ records with `reduce`, `None` without records
+    pub fn reduce_fastq_pair<R1, R2, O, F, Red>(&self, reader1: R1, reader2: R2, mut f: F, reduce: Red) -> Result<Option<O>, BioReaderError>
+    where
+        R1: Read + Send,
+        R2: Read + Send,
+        O: Send,
+        F: FnMut(&RefFastqRecord, &RefFastqRecord) -> O + Clone + Send,
+        Red: Fn(O, O) -> O + Clone + Send,
+    {
+        let reduce_local = reduce.clone();
+        let states = self.fastq_pair_states(reader1, reader2, || None, move |record1, record2, acc: &mut Option<O>| {
+            reduce_into(&reduce_local, acc, f(record1, record2))
+        })?;
+        Ok(states.into_iter().flatten().reduce(reduce))
+    }
+
+    /// Combine the results of `f` for all FASTA records with `reduce`, `None` without records
+    pub fn reduce_fasta<R, O, F, Red>(&self, reader: R, mut f: F, reduce: Red) -> Result<Option<O>, BioReaderError>
+    where
+        R: Read + Send,
+        O: Send,
+        F: FnMut(&RefFastaRecord) -> O + Clone + Send,
+        Red: Fn(O, O) -> O + Clone + Send,
+    {
+        let reduce_local = reduce.clone();
+        let states = self.fasta_states(reader, || None, move |record, acc: &mut Option<O>| {
+            reduce_into(&reduce_local, acc, f(record))
+        })?;
+        Ok(states.into_iter().flatten().reduce(reduce))
+    }
+
+    /// Map single-end FASTQ records on the workers and hand the results to `emit` on the calling thread.
+    ///
+    /// Returns the number of emitted results, an error returned by `emit` stops the run.
+    pub fn map_fastq<R, T, F, E>(&self, reader: R, mut f: F, emit: E) -> Result<u64, BioReaderError>
+    where
+        R: Read + Send,
+        T: Send,
+        F: FnMut(&RefFastqRecord) -> Option<T> + Clone + Send,
+        E: FnMut(T) -> Result<(), BioReaderError>,
+    {
+        self.map_chunks(emit_each(emit), |builder, tx| {
+            builder.fastq_chunks(
+                reader,
+                Vec::new,
+                move |record, results: &mut Vec<T>| {
+                    results.extend(f(record));
+                    Ok(())
+                },
+                send_chunk(tx),
+            )
+        })
+    }
+
+    /// Like [`ParallelReaderBuilder::map_fastq`] for pairs of FASTQ records
+    pub fn map_fastq_pair<R1, R2, T, F, E>(&self, reader1: R1, reader2: R2, mut f: F, emit: E) -> Result<u64, BioReaderError>
+    where
+        R1: Read + Send,
+        R2: Read + Send,
+        T: Send,
+        F: FnMut(&RefFastqRecord, &RefFastqRecord) -> Option<T> + Clone + Send,
+        E: FnMut(T) -> Result<(), BioReaderError>,
+    {
+        self.map_chunks(emit_each(emit), |builder, tx| {
+            builder.fastq_pair_chunks(
+                reader1,
+                reader2,
+                Vec::new,
+                move |record1, record2, results: &mut Vec<T>| {
+                    results.extend(f(record1, record2));
+                    Ok(())
+                },
+                send_chunk(tx),
+            )
+        })
+    }
+
+    /// Like [`ParallelReaderBuilder::map_fastq`] for FASTA records
+    pub fn map_fasta<R, T, F, E>(&self, reader: R, mut f: F, emit: E) -> Result<u64, BioReaderError>
+    where
+        R: Read + Send,
+        T: Send,
+        F: FnMut(&RefFastaRecord) -> Option<T> + Clone + Send,
+        E: FnMut(T) -> Result<(), BioReaderError>,
+    {
+        self.map_chunks(emit_each(emit), |builder, tx| {
+            builder.fasta_chunks(
+                reader,
+                Vec::new,
+                move |record, results: &mut Vec<T>| {
+                    results.extend(f(record));
+                    Ok(())
+                },
+                send_chunk(tx),
+            )
+        })
+    }
+
+    /// Run `workers` on a separate thread and pass every chunk they send to `emit_chunk`, in input order if requested.
+    ///
+    /// `emit_chunk` returns the number of items it emitted, their sum is returned.
+    pub(crate) fn map_chunks<C, E, W>(&self, mut emit_chunk: E, workers: W) -> Result<u64, BioReaderError>
+    where
+        C: Send,
+        E: FnMut(C) -> Result<u64, BioReaderError>,
+        W: FnOnce(&Self, SyncSender<(u64, C)>) -> Result<Vec<C>, BioReaderError> + Send,
+    {
+        // Bounds the number of finished chunks waiting for the calling thread
+        let (tx, rx) = mpsc::sync_channel::<(u64, C)>(2 * self.num_threads);
+        // In order, chunks finished after a slow one wait in the reorder buffer. The producer takes a
+        // permit for every chunk and gets it back once the chunk is emitted, which bounds the buffer.
+        let max_pending = 2 * self.num_threads;
+        let (permit_tx, permit_rx) = crossbeam_channel::bounded(max_pending);
+        let builder = match self.ordered {
+            true => {
+                (0..max_pending).for_each(|_| permit_tx.send(()).expect("Permits fit into the channel"));
+                Self { window: Some(permit_rx), ..self.clone() }
+            }
+            false => self.clone(),
+        };
+
+        std::thread::scope(|scope| {
+            let handle = scope.spawn(|| workers(&builder, tx));
+
+            let mut reorder = ReorderBuffer::new();
+            let mut emitted = 0;
+            let mut result = Ok(());
+            for (index, chunk) in rx.iter() {
+                result = match self.ordered {
+                    true => {
+                        reorder.push(index, chunk);
+                        std::iter::from_fn(|| reorder.pop()).try_for_each(|chunk| {
+                            // At most `max_pending` permits are out, the send cannot block
+                            let _ = permit_tx.send(());
+                            emitted += emit_chunk(chunk)?;
+                            Ok(())
+                        })
+                    }
+                    false => emit_chunk(chunk).map(|n| emitted += n),
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+            // Dropping the receiver stops workers that still have chunks to send, and the permits a waiting producer
+            drop(rx);
+            drop(permit_tx);
+
+            let workers = handle.join().expect("Worker thread panicked");
+            result?;
+            workers?;
+            Ok(emitted)
+        })
+    }
+
+    /// States of all workers of a single-end FASTQ run, each created by `init`
+    pub(crate) fn fastq_states<R, S, I, F>(&self, reader: R, init: I, mut f: F) -> Result<Vec<S>, BioReaderError>
+    where
+        R: Read + Send,
+        S: Send,
+        I: Fn() -> S,
+        F: FnMut(&RefFastqRecord, &mut S) + Clone + Send,
+    {
+        let f = move |record: &RefFastqRecord, state: &mut S| {
+            f(record, state);
+            Ok(())
+        };
+        self.fastq_chunks(reader, init, f, |_, _| true)
+    }
+
+    /// Like [`ParallelReaderBuilder::fastq_states`], `on_chunk` is called with the chunk index after every chunk
+    /// and stops the worker by returning false
+    pub(crate) fn fastq_chunks<R, S, I, F, C>(&self, reader: R, init: I, f: F, on_chunk: C) -> Result<Vec<S>, BioReaderError>
+    where
+        R: Read + Send,
+        S: Send,
+        I: Fn() -> S,
+        F: FnMut(&RefFastqRecord, &mut S) -> Result<(), WorkerError> + Clone + Send,
+        C: FnMut(u64, &mut S) -> bool + Clone + Send,
+    {
+        let tracker = Tracker::new(self);
+        let pool = BufferPool::new(self.chunk_size);
+        let (tx, rx) = crossbeam_channel::bounded(self.num_threads);
+        let (tracker, validation, pool) = (&tracker, &self.validation, &pool);
+
+        // This scope guarantees that all threads finish within the scope. This way, lifetimes of F and R do not need to be 'static
+        std::thread::scope(|scope| {
+            let mut byte_reader = FastqByteReader::new(tracker.decoder(scope, self.compression, reader)?, self.chunk_size)?
+                .with_max_buffer_size(self.max_record_length)
+                .with_multiline(self.multiline);
+            // The only thread reading the input, the workers receive filled chunks
+            let producer =
+                scope.spawn(move || tracker.cancel_on_error(|| produce(&mut byte_reader, pool, tx, || tracker.is_cancelled() || !tracker.wait_for_window())));
+            let mut threads = Vec::with_capacity(self.num_threads);
+            for _thread in 0..self.num_threads {
+                let mut fastq_reader = FastqReader::with_capacity(self.chunk_size);
+                let chunks = rx.clone();
+                let mut state = init();
+                let mut f_local = f.clone();
+                let mut on_chunk = on_chunk.clone();
+
+                threads.push(scope.spawn(move || {
+                    tracker.cancel_on_error(move || {
+                        while !tracker.is_cancelled() {
+                            let Ok(chunk) = chunks.recv() else {
+                                break;
+                            };
+                            pool.give(fastq_reader.load_chunk(chunk));
+                            tracker.chunk_loaded();
+                            let chunk = fastq_reader.chunk_index();
+                            let mut records = 0;
+                            let result = catch_panic(|| {
+                                while let Some(record) = fastq_reader.next()? {
+                                    if tracker.is_cancelled() {
+                                        break;
+                                    }
+                                    if let Some(byte) = validation.find_invalid(record.seq()) {
+                                        validation.reject(byte, fastq_reader.position())?;
+                                        continue;
+                                    }
+                                    f_local(&record, &mut state).map_err(|source| BioReaderError::WorkerFailed {
+                                        chunk,
+                                        position: fastq_reader.position(),
+                                        source,
+                                    })?;
+                                    records += 1;
+                                }
+                                Ok(())
+                            })
+                            .unwrap_or_else(|message| {
+                                Err(BioReaderError::WorkerPanicked { chunk, position: fastq_reader.position(), message })
+                            });
+                            let more = result.is_ok() && on_chunk(chunk, &mut state);
+                            // A failed chunk is done as well, it must not stay in flight
+                            tracker.chunk_done(records);
+                            result?;
+                            // The consumer of the results stopped
+                            if !more {
+                                break;
+                            }
+                        }
+                        Ok(state)
+                    })
+                }));
+            }
+            drop(rx);
+            join_workers(producer, threads)
+        })
+    }
+
+    /// States of all workers of a paired-end FASTQ run, each created by `init`
+    pub(crate) fn fastq_pair_states<R1, R2, S, I, F>(&self, reader1: R1, reader2: R2, init: I, mut f: F) -> Result<Vec<S>, BioReaderError>
+    where
+        R1: Read + Send,
+        R2: Read + Send,
+        S: Send,
+        I: Fn() -> S,
+        F: FnMut(&RefFastqRecord, &RefFastqRecord, &mut S) + Clone + Send,
+    {
+        let f = move |record1: &RefFastqRecord, record2: &RefFastqRecord, state: &mut S| {
+            f(record1, record2, state);
+            Ok(())
+        };
+        self.fastq_pair_chunks(reader1, reader2, init, f, |_, _| true)
+    }
+
+    pub(crate) fn fastq_pair_chunks<R1, R2, S, I, F, C>(&self, reader1: R1, reader2: R2, init: I, f: F, on_chunk: C) -> Result<Vec<S>, BioReaderError>
+    where
+        R1: Read + Send,
+        R2: Read + Send,
+        S: Send,
+        I: Fn() -> S,
+        F: FnMut(&RefFastqRecord, &RefFastqRecord, &mut S) -> Result<(), WorkerError> + Clone + Send,
+        C: FnMut(u64, &mut S) -> bool + Clone + Send,
+    {
+        let tracker = Tracker::new(self);
+        let pool = BufferPool::new(self.chunk_size);
+        let (tx, rx) = crossbeam_channel::bounded(self.num_threads);
+        let (tracker, validation, pool) = (&tracker, &self.validation, &pool);
+
+        std::thread::scope(|scope| {
+            let decoded1 = tracker.decoder(scope, self.compression, reader1)?;
+            let decoded2 = tracker.decoder(scope, self.compression, reader2)?;
+            let mut byte_reader = FastqPairedByteReader::new(decoded1, decoded2, self.chunk_size)
+                .with_max_buffer_size(self.max_record_length)
+                .with_multiline(self.multiline);
+            let producer =
+                scope.spawn(move || tracker.cancel_on_error(|| produce_pair(&mut byte_reader, pool, tx, || tracker.is_cancelled() || !tracker.wait_for_window())));
+            let mut threads = Vec::with_capacity(self.num_threads);
+            for _thread in 0..self.num_threads {
+                let mut fastq_reader = PairedFastqReader::<DynRead>::with_capacity(self.chunk_size);
+                let chunks = rx.clone();
+                let mut state = init();
+                let mut f_local = f.clone();
+                let mut on_chunk = on_chunk.clone();
+
+                threads.push(scope.spawn(move || {
+                    tracker.cancel_on_error(move || {
+                        while !tracker.is_cancelled() {
+                            let Ok((chunk1, chunk2)) = chunks.recv() else {
+                                break;
+                            };
+                            let (buffer1, buffer2) = fastq_reader.load_chunks(chunk1, chunk2);
+                            pool.give(buffer1);
+                            pool.give(buffer2);
+                            tracker.chunk_loaded();
+                            let chunk = fastq_reader.chunk_index();
+                            let mut records = 0;
+                            let result = catch_panic(|| {
+                                while let Some((record1, record2)) = fastq_reader.next_in_batch()? {
+                                    if tracker.is_cancelled() {
+                                        break;
+                                    }
+                                    if let Some((byte, mate)) = validation.find_invalid_pair(record1.seq(), record2.seq()) {
+                                        let (position1, position2) = fastq_reader.positions();
+                                        validation.reject(byte, if mate == 1 { position1 } else { position2 })?;
+                                        continue;
+                                    }
+                                    f_local(&record1, &record2, &mut state).map_err(|source| BioReaderError::WorkerFailed {
+                                        chunk,
+                                        position: fastq_reader.positions().0,
+                                        source,
+                                    })?;
+                                    records += 1;
+                                }
+                                Ok(())
+                            })
+                            .unwrap_or_else(|message| {
+                                Err(BioReaderError::WorkerPanicked { chunk, position: fastq_reader.positions().0, message })
+                            });
+                            let more = result.is_ok() && on_chunk(chunk, &mut state);
+                            tracker.chunk_done(records);
+                            result?;
+                            if !more {
+                                break;
+                            }
+                        }
+                        Ok(state)
+                    })
+                }));
+            }
+            drop(rx);
+            join_workers(producer, threads)
+        })
+    }
+
+    /// States of all workers of a FASTA run, each created by `init`
+    pub(crate) fn fasta_states<R, S, I, F>(&self, reader: R, init: I, mut f: F) -> Result<Vec<S>, BioReaderError>
+    where
+        R: Read + Send,
+        S: Send,
+        I: Fn() -> S,
+        F: FnMut(&RefFastaRecord, &mut S) + Clone + Send,
+    {
+        let f = move |record: &RefFastaRecord, state: &mut S| {
+            f(record, state);
+            Ok(())
+        };
+        self.fasta_chunks(reader, init, f, |_, _| true)
+    }
+
+    pub(crate) fn fasta_chunks<R, S, I, F, C>(&self, reader: R, init: I, f: F, on_chunk: C) -> Result<Vec<S>, BioReaderError>
+    where
+        R: Read + Send,
+        S: Send,
+        I: Fn() -> S,
+        F: FnMut(&RefFastaRecord, &mut S) -> Result<(), WorkerError> + Clone + Send,
+        C: FnMut(u64, &mut S) -> bool + Clone + Send,
+    {
+        let tracker = Tracker::new(self);
+        let pool = BufferPool::new(self.chunk_size);
+        let (tx, rx) = crossbeam_channel::bounded(self.num_threads);
+        let (tracker, validation, pool) = (&tracker, &self.validation, &pool);
+
+        std::thread::scope(|scope| {
+            let mut byte_reader = FastaByteReader::new(tracker.decoder(scope, self.compression, reader)?, self.chunk_size)?
+                .with_max_buffer_size(self.max_record_length);
+            let producer =
+                scope.spawn(move || tracker.cancel_on_error(|| produce(&mut byte_reader, pool, tx, || tracker.is_cancelled() || !tracker.wait_for_window())));
+            let mut threads = Vec::with_capacity(self.num_threads);
+            for _thread in 0..self.num_threads {
+                let mut fasta_reader = FastaReader::with_capacity(self.chunk_size);
+                let chunks = rx.clone();
+                let mut state = init();
+                let mut f_local = f.clone();
+                let mut on_chunk = on_chunk.clone();
+
+                threads.push(scope.spawn(move || {
+                    tracker.cancel_on_error(move || {
+                        while !tracker.is_cancelled() {
+                            let Ok(chunk) = chunks.recv() else {
+                                break;
+                            };
+                            pool.give(fasta_reader.load_chunk(chunk));
+                            tracker.chunk_loaded();
+                            let chunk = fasta_reader.chunk_index();
+                            let mut records = 0;
+                            let result = catch_panic(|| {
+                                while let Some(record) = fasta_reader.next_ref()? {
+                                    if tracker.is_cancelled() {
+                                        break;
+                                    }
+                                    if let Some(byte) = record.seq_lines().find_map(|line| validation.find_invalid(line)) {
+                                        validation.reject(byte, fasta_reader.position())?;
+                                        continue;
+                                    }
+                                    f_local(&record, &mut state).map_err(|source| BioReaderError::WorkerFailed {
+                                        chunk,
+                                        position: fasta_reader.position(),
+                                        source,
+                                    })?;
+                                    records += 1;
+                                }
+                                Ok(())
+                            })
+                            .unwrap_or_else(|message| {
+                                Err(BioReaderError::WorkerPanicked { chunk, position: fasta_reader.position(), message })
+                            });
+                            let more = result.is_ok() && on_chunk(chunk, &mut state);
+                            tracker.chunk_done(records);
+                            result?;
+                            if !more {
+                                break;
+                            }
+                        }
+                        Ok(state)
+                    })
+                }));
+            }
+            drop(rx);
+            join_workers(producer, threads)
+        })
+    }
+}
+
+/// Chunk callback of the workers of [`ParallelReaderBuilder::map_chunks`], false once the receiver is gone
+pub(crate) fn send_chunk<C: Default + Send>(tx: SyncSender<(u64, C)>) -> impl FnMut(u64, &mut C) -> bool + Clone + Send {
+    move |index, chunk| tx.send((index, std::mem::take(chunk))).is_ok()
+}
+
+/// Emit the results of a chunk one by one
+fn emit_each<T>(mut emit: impl FnMut(T) -> Result<(), BioReaderError>) -> impl FnMut(Vec<T>) -> Result<u64, BioReaderError> {
+    move |results| {
+        let n = results.len() as u64;
+        results.into_iter().try_for_each(&mut emit)?;
+        Ok(n)
+    }
+}
+
+#[inline]
+fn reduce_into<O>(reduce: &impl Fn(O, O) -> O, acc: &mut Option<O>, value: O) {
+    *acc = Some(match acc.take() {
+        Some(acc) => reduce(acc, value),
+        None => value,
+    });
+}
+
+fn merge_all<S: Default + Merge>(states: Vec<S>) -> S {
+    let mut global_state = S::default();
+    for mut state in states {
+        global_state.merge_from(&mut state);
+    }
+    global_state
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Cursor, Write},
+        sync::atomic::AtomicU64,
+    };
+
+    use flate2::write::GzEncoder;
+
+    use super::*;
+    use crate::validation::{OnInvalid, Validation};
+
+    #[test]
+    fn test_builder_runs() {
+        let fastq = "@read\nACGT\n+\nIIII\n".repeat(500);
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(fastq.as_bytes()).unwrap();
+        let gzip = encoder.finish().unwrap();
+
+        let chunks = Arc::new(AtomicU64::new(0));
+        let chunks_local = chunks.clone();
+        let builder = ParallelReaderBuilder::new()
+            .num_threads(3)
+            .chunk_size(256)
+            .progress(move |progress| {
+                chunks_local.fetch_max(progress.chunks, Ordering::Relaxed);
+            });
+
+        let bases: usize = builder
+            .run_fastq(Cursor::new(gzip.clone()), |record, bases: &mut usize| *bases += record.seq().len())
+            .unwrap();
+        assert_eq!(bases, 2000);
+        assert!(chunks.load(Ordering::Relaxed) > 1);
+
+        let pairs: usize = builder
+            .run_fastq_pair(Cursor::new(gzip), Cursor::new(fastq), |_, _, pairs: &mut usize| *pairs += 1)
+            .unwrap();
+        assert_eq!(pairs, 500);
+
+        let fasta = ">chr\nACGT\nACGT\n".repeat(100);
+        let bases: usize = builder
+            .run_fasta(Cursor::new(fasta.clone()), |record, bases: &mut usize| *bases += record.seq_len())
+            .unwrap();
+        assert_eq!(bases, 800);
+
+        // Every sequence line of a borrowed record is validated
+        let dna = builder.clone().validation(ValidationPolicy::new(Validation::Dna, OnInvalid::Error));
+        assert!(dna.run_fasta(Cursor::new(fasta.clone()), |_, _: &mut ()| ()).is_ok());
+        let err = dna.run_fasta(Cursor::new(fasta + ">chr\nACGT\nACXT\n"), |_, _: &mut ()| ()).unwrap_err();
+        assert!(matches!(err, BioReaderError::InvalidByte { byte: b'X', .. }), "{err}");
+
+        let err = builder
+            .clone()
+            .max_record_length(512)
+            .run_fastq(Cursor::new(format!("@read\n{}\n+\n{}\n", "A".repeat(600), "I".repeat(600))), |_, _: &mut usize| ())
+            .unwrap_err();
+        assert!(matches!(err, BioReaderError::RecordTooLarge { .. }), "{err}");
+
+        let wrapped = "@read\nACGT\nAC\n+\n@III\nII\n".repeat(300);
+        let bases: usize = builder
+            .clone()
+            .multiline_fastq(true)
+            .run_fastq(Cursor::new(wrapped), |record, bases: &mut usize| *bases += record.seq().len())
+            .unwrap();
+        assert_eq!(bases, 1800);
+
+        // A chunk size of 0 still reads every record
+        let tiny = builder.clone().chunk_size(0);
+        let records: usize = tiny.run_fastq(Cursor::new("@read\nACGT\n+\nIIII\n".repeat(8)), |_, records: &mut usize| *records += 1).unwrap();
+        assert_eq!(records, 8);
+        let records: usize = tiny.run_fasta(Cursor::new(">chr\nACGT\nACGT\n".repeat(10)), |_, records: &mut usize| *records += 1).unwrap();
+        assert_eq!(records, 10);
+    }
+
+    #[test]
+    fn test_decompression_threads() {
+        let fastq = "@read\nACGT\n+\nIIII\n".repeat(2000);
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(fastq.as_bytes()).unwrap();
+        let gzip = encoder.finish().unwrap();
+        let bgzf = crate::bgzf::tests::bgzf(fastq.as_bytes());
+
+        let counters = Arc::new(ProgressCounters::new());
+        let builder = ParallelReaderBuilder::new()
+            .num_threads(3)
+            .chunk_size(256)
+            .decompression_threads(4)
+            .progress_counters(counters.clone());
+        for input in [&gzip, &bgzf] {
+            // Borrowed input works as well
+            let records: u64 = builder.run_fastq(&input[..], |_, records: &mut u64| *records += 1).unwrap();
+            assert_eq!(records, 2000);
+        }
+        let pairs: u64 = builder
+            .clone()
+            .compression(Compression::Bgzf)
+            .run_fastq_pair(&bgzf[..], &bgzf[..], |_, _, pairs: &mut u64| *pairs += 1)
+            .unwrap();
+        assert_eq!(pairs, 2000);
+
+        let progress = counters.snapshot();
+        assert_eq!(progress.bytes_read, (gzip.len() + 3 * bgzf.len()) as u64);
+        assert_eq!(progress.bytes_decompressed, 4 * fastq.len() as u64);
+    }
+
+    #[test]
+    fn test_progress() {
+        let fastq = "@read\nACGT\n+\nIIII\n".repeat(1000);
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(fastq.as_bytes()).unwrap();
+        let gzip = encoder.finish().unwrap();
+
+        let counters = Arc::new(ProgressCounters::new());
+        let builder = ParallelReaderBuilder::new().num_threads(3).chunk_size(256).progress_counters(counters.clone());
+        let records: u64 = builder.run_fastq(Cursor::new(gzip.clone()), |_, records: &mut u64| *records += 1).unwrap();
+        let progress = counters.snapshot();
+        assert_eq!(records, 1000);
+        assert_eq!((progress.bytes_read, progress.bytes_decompressed), (gzip.len() as u64, fastq.len() as u64));
+        assert_eq!((progress.records, progress.chunks_in_flight), (1000, 0));
+        assert!(progress.chunks > 1);
+
+        // Both mates count towards the bytes, a pair is one record
+        let counters = Arc::new(ProgressCounters::new());
+        let builder = builder.progress_counters(counters.clone());
+        builder.run_fastq_pair(Cursor::new(gzip), Cursor::new(fastq.clone()), |_, _, _: &mut ()| ()).unwrap();
+        let progress = counters.snapshot();
+        assert_eq!(progress.bytes_decompressed, 2 * fastq.len() as u64);
+        assert_eq!((progress.records, progress.chunks_in_flight), (1000, 0));
+
+        let fasta = ">chr\nACGT\nACGT\n".repeat(100);
+        let counters = Arc::new(ProgressCounters::new());
+        let builder = builder.progress_counters(counters.clone());
+        builder.run_fasta(Cursor::new(fasta.clone()), |_, _: &mut ()| ()).unwrap();
+        let progress = counters.snapshot();
+        assert_eq!((progress.bytes_read, progress.bytes_decompressed), (fasta.len() as u64, fasta.len() as u64));
+        assert_eq!(progress.records, 100);
+    }
+
+    #[test]
+    fn test_ordered_map() {
+        let fastq: String = (0..2000).map(|i| format!("@read{}\nACGT\n+\nIIII\n", i)).collect();
+        let builder = ParallelReaderBuilder::new().num_threads(4).chunk_size(128).ordered(true);
+
+        let mut names = Vec::new();
+        let emitted = builder
+            .map_fastq(
+                Cursor::new(fastq.clone()),
+                |record| Some(record.head().to_vec()),
+                |name| {
+                    names.push(name);
+                    Ok(())
+                },
+            )
+            .unwrap();
+        let expected: Vec<Vec<u8>> = (0..2000).map(|i| format!("read{}", i).into_bytes()).collect();
+        assert_eq!(emitted, 2000);
+        assert_eq!(names, expected);
+
+        // Filtered pairs keep their order as well
+        let mut pairs = Vec::new();
+        builder
+            .map_fastq_pair(
+                Cursor::new(fastq.clone()),
+                Cursor::new(fastq),
+                |record1, record2| (record1.head().ends_with(b"0")).then(|| (record1.head().to_vec(), record2.head().to_vec())),
+                |pair| {
+                    pairs.push(pair);
+                    Ok(())
+                },
+            )
+            .unwrap();
+        let expected: Vec<_> = expected.into_iter().filter(|name| name.ends_with(b"0")).map(|name| (name.clone(), name)).collect();
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn test_ordered_map_window() {
+        let fastq: String = (0..2000).map(|i| format!("@read{}\nACGT\n+\nIIII\n", i)).collect();
+        let counters = Arc::new(ProgressCounters::new());
+        let builder = ParallelReaderBuilder::new().num_threads(2).chunk_size(128).ordered(true).progress_counters(counters.clone());
+
+        // The first chunk is slow, the others must not pile up behind it
+        let mut chunks_done = None;
+        let emitted = builder
+            .map_fastq(
+                Cursor::new(fastq),
+                |record| {
+                    if record.head() == b"read0" {
+                        std::thread::sleep(Duration::from_millis(200));
+                    }
+                    Some(())
+                },
+                |()| {
+                    chunks_done.get_or_insert(counters.snapshot().chunks);
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(emitted, 2000);
+        assert!(chunks_done.unwrap() <= 4, "{chunks_done:?}");
+
+        // A failed chunk is never emitted, the producer waiting for its permit stops nonetheless
+        let fastq = "@read\nACGT\n+\nIIII\n".repeat(100) + "@read\nACGT\n-\nIIII\n" + &"@read\nACGT\n+\nIIII\n".repeat(1000);
+        let err = builder.map_fastq(Cursor::new(fastq), |_| Some(()), |()| Ok(())).unwrap_err();
+        assert!(matches!(err, BioReaderError::MissingSeparator { .. }), "{err}");
+    }
+
+    #[test]
+    fn test_reduce() {
+        let fasta = ">a\nACGT\n>b\nACGTACGTAC\n>c\nAC\n".repeat(50);
+        let builder = ParallelReaderBuilder::new().num_threads(3).chunk_size(64);
+        let longest = builder.reduce_fasta(Cursor::new(fasta), |record| record.seq_len(), std::cmp::max).unwrap();
+        assert_eq!(longest, Some(10));
+
+        let none = builder.reduce_fastq(Cursor::new(""), |record| record.seq().len(), std::cmp::max).unwrap();
+        assert_eq!(none, None);
+    }
+
+    #[test]
+    fn test_worker_failure() {
+        let fastq: String = (0..5000).map(|i| format!("@read{}\nACGT\n+\nIIII\n", i)).collect();
+        let builder = ParallelReaderBuilder::new().num_threads(4).chunk_size(256);
+        let seen = Arc::new(AtomicU64::new(0));
+
+        let seen_local = seen.clone();
+        let err = builder
+            .run_fastq(Cursor::new(fastq.clone()), move |record, _: &mut usize| {
+                seen_local.fetch_add(1, Ordering::Relaxed);
+                assert!(record.head() != b"read1000", "Bad record");
+            })
+            .unwrap_err();
+        let BioReaderError::WorkerPanicked { position, message, .. } = err else {
+            panic!("Expected a panic error, got {err}");
+        };
+        assert_eq!(position.record_number, 1001);
+        assert_eq!(message, "Bad record");
+        // The other workers stop early
+        assert!(seen.load(Ordering::Relaxed) < 5000);
+
+        let counters = Arc::new(ProgressCounters::new());
+        let err = builder
+            .clone()
+            .progress_counters(counters.clone())
+            .try_run_fastq(Cursor::new(fastq), |record, _: &mut usize| match record.head() {
+                b"read42" => Err("Unexpected read"),
+                _ => Ok(()),
+            })
+            .unwrap_err();
+        assert!(matches!(err, BioReaderError::WorkerFailed { position, .. } if position.record_number == 43), "{err}");
+        assert_eq!(std::error::Error::source(&err).unwrap().to_string(), "Unexpected read");
+        // Failed chunks do not stay in flight
+        assert_eq!(counters.snapshot().chunks_in_flight, 0);
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let fastq: String = (0..20000).map(|i| format!("@read{}\nACGT\n+\nIIII\n", i)).collect();
+        let token = CancellationToken::new();
+        let builder = ParallelReaderBuilder::new().num_threads(4).chunk_size(256).ordered(true).cancellation(token.clone());
+
+        // Take the first 100 records like `head`
+        let mut names = Vec::new();
+        builder
+            .map_fastq(
+                Cursor::new(fastq),
+                |record| Some(record.head().to_vec()),
+                |name| {
+                    if names.len() == 100 {
+                        token.cancel();
+                    } else {
+                        names.push(name);
+                    }
+                    Ok(())
+                },
+            )
+            .unwrap();
+        let expected: Vec<Vec<u8>> = (0..100).map(|i| format!("read{}", i).into_bytes()).collect();
+        assert_eq!(names, expected);
+
+        // A cancelled token stops a run before its first record
+        let count: usize = builder.run_fastq(Cursor::new("@read\nACGT\n+\nIIII\n"), |_, count: &mut usize| *count += 1).unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -1,148 +1,53 @@
-use std::sync::{mpsc, Arc, Mutex};
-
 use crate::{
-    fasta_byte_reader::FastaByteReader, fasta_reader::FastaReader, fastq_byte_reader::{FastqByteReader, FastqPairedByteReader}, fastq_reader::{self, FastqReader, PairedFastqReader}, sequence::{fasta_record::OwnedFastaRecord, fastq_record::RefFastqRecord}
+    compression::Compression,
+    error::BioReaderError,
+    parallel::builder::ParallelReaderBuilder,
+    sequence::{fasta_record::OwnedFastaRecord, fastq_record::RefFastqRecord},
+    validation::ValidationPolicy,
 };
 
-pub fn read_fastq_par<G, T>(
+/// Builder for the functions below, their input is expected to be decompressed already
+fn builder(buffer_size: usize, num_threads: u32, validation: ValidationPolicy) -> ParallelReaderBuilder {
+    ParallelReaderBuilder::new()
+        .num_threads(num_threads as usize)
+        .chunk_size(buffer_size)
+        .validation(validation)
+        .compression(Compression::None)
+}
+
+/// Merges the results of `f` for all records with [`Merge`], e.g. sums them for numbers
+pub fn read_fastq_par<G, T, O>(
     file: T,
     buffer_size: usize,
     num_threads: u32,
+    validation: ValidationPolicy,
     f: G,
-) -> Result<usize, std::io::Error>
+) -> Result<O, BioReaderError>
 where
-    G: Fn(&RefFastqRecord) -> usize + Clone + Send,
+    G: Fn(&RefFastqRecord) -> O + Clone + Send,
     T: std::io::Read + std::marker::Send,
+    O: Default + Send + Merge,
 {
-
-    let byte_reader = Arc::new(Mutex::new(FastqByteReader::new(file, buffer_size)?));
-    let mut total = 0;
-    std::thread::scope(|scope| { 
-
-
-        let mut threads = Vec::new();
-
-        let (tx, rx) = mpsc::channel::<usize>();
-
-        // Results thread
-        scope.spawn(move || {
-            let mut total_result = 0;
-            for e in rx {
-                total_result += e;
-            }
-            println!("Totale resultate: {}", total_result);
-        });
-
-        for _thread in 0..num_threads {
-            let mut fastq_reader = FastqReader::with_capacity(buffer_size);
-            let mut reader_clone = byte_reader.clone(); // Need to clone for move
-            let mut count = 0;
-            let mut result_buffer = 0;
-            let f_clone = f.clone();
-            let tx: mpsc::Sender<usize> = tx.clone();
-
-            threads.push(scope.spawn(move || {
-                while let Some(()) = fastq_reader
-                    .load_batch_par(&mut reader_clone)
-                    .expect("Batch is invalid")
-                {
-                    while let Some(record) = fastq_reader.next() {
-                        count += 1;
-                        if !record.valid() {
-                            panic!("Invalid record {}", record)
-                        }
-                        result_buffer += f_clone(&record);
-
-                        if count > 10_000 {
-                            let _res = tx.send(result_buffer);
-                            result_buffer = 0;
-                            count -= 10_000;
-                        }
-                    }
-                }
-                count
-            }));
-        }
-
-        for thread_guard in threads {
-            total += thread_guard.join().unwrap();
-        }
-
-    
-    });
-    Ok(total)
+    builder(buffer_size, num_threads, validation).run_fastq(file, move |record, result: &mut O| result.merge_from(&mut f(record)))
 }
 
+/// Merges the results of `f` for all pairs with [`Merge`]
 pub fn read_fastq_pair_par<G, T, O>(
     file1: T,
     file2: T,
     buffer_size: usize,
     num_threads: u32,
-    f: G,
-) -> Result<u64, std::io::Error>
+    validation: ValidationPolicy,
+    mut f: G,
+) -> Result<O, BioReaderError>
 where
     G: FnMut(&RefFastqRecord, &RefFastqRecord) -> O + Clone + Send,
     T: std::io::Read + std::marker::Send,
+    O: Default + Send + Merge,
 {
-    let mut total = 0;
-
-    // This scope guarantees that all threads finish within the scope. This way, lifetimes of G and T do not need to be 'static
-    std::thread::scope(|scope| { 
-        let byte_reader = Arc::new(Mutex::new(FastqPairedByteReader::new(file1, file2, buffer_size)));
-        let mut threads = Vec::new();
-
-        let (tx, rx) = mpsc::channel::<usize>();
-
-        // Results thread. Clarify what this wants to do
-        scope.spawn(move || {
-            let mut total_result = 0;
-            for e in rx {
-                total_result += e;
-            }
-            println!("Totale result: {}", total_result);
-        });
-
-        for _thread in 0..num_threads {
-            let mut fastq_reader = PairedFastqReader::new(byte_reader.clone(), buffer_size);
-            let mut count = 0;
-            let mut count2 = 0;
-            let mut result_buffer = 0;
-            let mut f_clone = f.clone();
-            let tx: mpsc::Sender<usize> = tx.clone();
-            
-            threads.push(scope.spawn(move || {
-                while let Some((record1, record2)) = fastq_reader.next() {
-                    count += 1;
-                    if !record1.valid_extended() {
-                        panic!("Invalid record {}", record1)
-                    }
-                    if !record2.valid_extended() {
-                        panic!("Invalid record {}", record2)
-                    }
-
-                    f_clone(&record1, &record2);
-                    count2 += 1;
-
-                    if count > 10_000 {
-                        let _res = tx.send(result_buffer);
-                        result_buffer = 0;
-                        // count -= 10_000;
-                    }
-                }
-                println!("Read count: {}", count);
-
-                let _ = tx.send(result_buffer);
-                // count2
-                (count2, f_clone)
-            }));
-        }
-        
-        for thread_guard in threads {
-            let (count, _closure) = thread_guard.join().unwrap();
-            total += count;
-        }
-    });
-    Ok(total)
+    builder(buffer_size, num_threads, validation).run_fastq_pair(file1, file2, move |record1, record2, result: &mut O| {
+        result.merge_from(&mut f(record1, record2))
+    })
 }
 
 
@@ -150,357 +55,190 @@ pub fn read_fastq_single_end_state_par<G, T, State>(
     file: T,
     buffer_size: usize,
     num_threads: u32,
+    validation: ValidationPolicy,
     f: G,
-) -> Result<State, std::io::Error>
+) -> Result<State, BioReaderError>
 where
-    G: FnMut(&RefFastqRecord, &mut State) -> () + Clone + Send,
+    G: FnMut(&RefFastqRecord, &mut State) + Clone + Send,
     T: std::io::Read + std::marker::Send,
     State: Default + Send + Merge,
 {
-
-    let byte_reader = Arc::new(Mutex::new(FastqByteReader::new(file, buffer_size)?));
-    let mut total = 0;
-
-    let mut global_state = State::default();
-
-    std::thread::scope(|scope| { 
-
-
-        let mut threads = Vec::new();
-
-        // let (tx, rx) = mpsc::channel::<usize>();
-
-        // // Results thread
-        // scope.spawn(move || {
-        //     let mut total_result = 0;
-        //     for e in rx {
-        //         total_result += e;
-        //     }
-        //     println!("Totale resultate: {}", total_result);
-        // });
-
-        for _thread in 0..num_threads {
-            let mut fastq_reader = FastqReader::with_capacity(buffer_size);
-            let mut reader_local = byte_reader.clone(); // Need to clone for move
-            let mut count = 0;
-            let mut state = State::default();
-            let mut result_buffer = 0;
-            let mut f_local = f.clone();
-            // let tx: mpsc::Sender<usize> = tx.clone();
-
-            threads.push(scope.spawn(move || {
-                while let Some(()) = fastq_reader
-                    .load_batch_par(&mut reader_local)
-                    .expect("Batch is invalid")
-                {
-                    while let Some(record) = fastq_reader.next() {
-                        count += 1;
-                        if !record.valid_extended() {
-                            panic!("Invalid record {}", record)
-                        }
-                        
-                        f_local(&record, &mut state);
-
-                        // if count > 10_000 {
-                        //     let _res = tx.send(result_buffer);
-                        //     result_buffer = 0;
-                        //     count -= 10_000;
-                        // }
-                    }
-                }
-                state
-            }));
-        }
-
-        
-        for thread_guard in threads {
-            let mut state = match thread_guard.join() {
-                Ok(state) => state,
-                Err(err) => panic!("Error {:?}", err),
-            };
-            global_state.merge_from(&mut state);
-        }
-
-    
-    });
-    Ok(global_state)
+    builder(buffer_size, num_threads, validation).run_fastq(file, f)
 }
 
 
+/// Every worker starts from a clone of `global_state`, the results are merged back into it
 pub fn read_fastq_paired_end_state_par<G, T, State>(
     file1: T,
     file2: T,
     buffer_size: usize,
     num_threads: u32,
+    validation: ValidationPolicy,
     mut global_state: State,
     f: G,
-) -> Result<State, std::io::Error>
+) -> Result<State, BioReaderError>
 where
-    G: FnMut(&RefFastqRecord, &RefFastqRecord, &mut State) -> () + Clone + Send,
+    G: FnMut(&RefFastqRecord, &RefFastqRecord, &mut State) + Clone + Send,
     T: std::io::Read + std::marker::Send,
     State: Default + Clone + Send + Merge,
 {
-    let mut total = 0;
-
-    // let mut global_state = State::default();
-
-
-
-    // This scope guarantees that all threads finish within the scope. This way, lifetimes of G and T do not need to be 'static
-    std::thread::scope(|scope| { 
-        let byte_reader = Arc::new(Mutex::new(FastqPairedByteReader::new(file1, file2, buffer_size)));
-        let mut threads = Vec::new();
-
-
-
-        for _thread in 0..num_threads {
-            let mut fastq_reader = PairedFastqReader::new(byte_reader.clone(), buffer_size);
-            let result_buffer = 0;
-            let mut state = global_state.clone();
-            let mut f_local = f.clone();
-            // let tx: mpsc::Sender<usize> = tx.clone();
-            
-            threads.push(scope.spawn(move || {
-                while let Some((record1, record2)) = fastq_reader.next() {
-                    if !record1.valid_extended() {
-                        panic!("Invalid record {}", record1)
-                    }
-                    if !record2.valid_extended() {
-                        panic!("Invalid record {}", record2)
-                    }
-
-                    f_local(&record1, &record2, &mut state);
-                }
-
-                // let _ = tx.send(result_buffer);
-
-                state
-            }));
-        }
-        
-        for thread_guard in threads {
-            let mut state = match thread_guard.join() {
-                Ok(state) => state,
-                Err(err) => panic!("Error {:?}", err),
-            };
-            global_state.merge_from(&mut state);
-        }
-    });
+    let states = builder(buffer_size, num_threads, validation).fastq_pair_states(file1, file2, || global_state.clone(), f)?;
+    for mut state in states {
+        global_state.merge_from(&mut state);
+    }
     Ok(global_state)
 }
 
-// pub fn read_fastq_paired_end_state_par<G, T, State>(
-//     file1: T,
-//     file2: T,
-//     buffer_size: usize,
-//     num_threads: u32,
-//     f: G,
-// ) -> Result<State, std::io::Error>
-// where
-//     G: FnMut(&RefFastqRecord, &RefFastqRecord, &mut State) -> () + Clone + Send,
-//     T: std::io::Read + std::marker::Send,
-//     State: Default + Send + Merge,
-// {
-//     let mut total = 0;
-
-//     let mut global_state = State::default();
-
-//     // This scope guarantees that all threads finish within the scope. This way, lifetimes of G and T do not need to be 'static
-//     std::thread::scope(|scope| { 
-//         let byte_reader = Arc::new(Mutex::new(FastqPairedByteReader::new(file1, file2, buffer_size)));
-//         let mut threads = Vec::new();
-
-
-
-//         for _thread in 0..num_threads {
-//             let mut fastq_reader = PairedFastqReader::new(byte_reader.clone(), buffer_size);
-//             let result_buffer = 0;
-//             let mut state = State::default();
-//             let mut f_local = f.clone();
-//             // let tx: mpsc::Sender<usize> = tx.clone();
-            
-//             threads.push(scope.spawn(move || {
-//                 while let Some((record1, record2)) = fastq_reader.next() {
-//                     if !record1.valid_extended() {
-//                         panic!("Invalid record {}", record1)
-//                     }
-//                     if !record2.valid_extended() {
-//                         panic!("Invalid record {}", record2)
-//                     }
-
-//                     f_local(&record1, &record2, &mut state);
-//                 }
-
-//                 // let _ = tx.send(result_buffer);
-
-//                 state
-//             }));
-//         }
-        
-//         for thread_guard in threads {
-//             let mut state = match thread_guard.join() {
-//                 Ok(state) => state,
-//                 Err(err) => panic!("Error {:?}", err),
-//             };
-//             global_state.merge_from(&mut state);
-//         }
-//     });
-//     Ok(global_state)
-// }
-
-
-
-
-pub fn read_fasta_par<G, T>(
+/// Merges the results of `f` for all records with [`Merge`]
+pub fn read_fasta_par<G, T, O>(
     file: T,
     buffer_size: usize,
     num_threads: u32,
+    validation: ValidationPolicy,
     f: G,
-) -> Result<usize, std::io::Error>
+) -> Result<O, BioReaderError>
 where
-    G: Fn(&OwnedFastaRecord) -> usize + Clone + Send,
+    G: Fn(&OwnedFastaRecord) -> O + Clone + Send,
     T: std::io::Read + std::marker::Send,
+    O: Default + Send + Merge,
 {
+    // The closure is cloned per worker, so every worker copies its records into a buffer of its own
+    let mut owned = OwnedFastaRecord::new();
+    builder(buffer_size, num_threads, validation).run_fasta(file, move |record, result: &mut O| {
+        record.copy_into(&mut owned);
+        result.merge_from(&mut f(&owned))
+    })
+}
 
-    let byte_reader = Arc::new(Mutex::new(FastaByteReader::new(file, buffer_size)?));
-    let mut total = 0;
-    std::thread::scope(|scope| { 
-
-
-        let mut threads = Vec::new();
 
-        let (tx, rx) = mpsc::channel::<usize>();
+pub trait Merge {
+    fn merge_from(&mut self, other: &mut Self);
+}
 
-        // Results thread
-        scope.spawn(move || {
-            let mut total_result = 0;
-            for e in rx {
-                total_result += e;
+macro_rules! impl_merge_sum {
+    ($($t:ty),*) => {
+        $(impl Merge for $t {
+            fn merge_from(&mut self, other: &mut Self) {
+                *self += *other;
             }
-            println!("Totale resultate: {}", total_result);
-        });
-
-        for _thread in 0..num_threads {
-            let mut fasta_reader = FastaReader::with_capacity(buffer_size);
-            let mut reader_clone = byte_reader.clone(); // Need to clone for move
-            let mut count = 0;
-            let mut result_buffer = 0;
-            let f_clone = f.clone();
-            let tx: mpsc::Sender<usize> = tx.clone();
-
-            let mut record = OwnedFastaRecord::new();
-            threads.push(scope.spawn(move || {
-
-                while let Some(()) = fasta_reader
-                    .load_batch_par(&mut reader_clone)
-                    .expect("Batch is invalid")
-                {
-                    while let Some(_) = fasta_reader.next(&mut record) {
-                        count += 1;
-                        if !record.valid_extended() {
-                            panic!("Invalid record {}", record.to_string())
-                        }
-                        result_buffer += f_clone(&record);
-
-                        if count > 10_000 {
-                            let _res = tx.send(result_buffer);
-                            result_buffer = 0;
-                        }
-                    }
-                }
-                let _res = tx.send(result_buffer);
-                count
-            }));
-        }
+        })*
+    };
+}
 
-        for thread_guard in threads {
-            let result = thread_guard.join().unwrap();
-            total += result;
-        }
+impl_merge_sum!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
 
-    
-    });
-    Ok(total)
+impl Merge for () {
+    fn merge_from(&mut self, _other: &mut Self) {}
 }
 
-
-pub trait Merge {
-    fn merge_from(self: &mut Self, other: &mut Self);
+impl<T> Merge for Vec<T> {
+    fn merge_from(&mut self, other: &mut Self) {
+        self.append(other);
+    }
 }
 
 
+/// Like [`read_fasta_par`], but `h` receives the buffer of every worker once all records are read.
+///
+/// Kept for existing callers that consume the per-worker buffers one by one instead of merging them,
+/// new code should use [`ParallelReaderBuilder::run_fasta`], which borrows the records instead of copying them.
 pub fn read_fasta_par2<G, H, T, B>(
     file: T,
     buffer_size: usize,
     num_threads: u32,
+    validation: ValidationPolicy,
     f: G,
     h: H,
-) -> Result<usize, std::io::Error>
+) -> Result<usize, BioReaderError>
 where
     G: Fn(&OwnedFastaRecord, &mut B) -> usize + Clone + Send,
     H: Fn(B) -> Option<()> + Clone + Send,
     T: std::io::Read + std::marker::Send,
     B: Clone + Send + Default + Merge,
 {
+    let mut owned = OwnedFastaRecord::new();
+    let states = builder(buffer_size, num_threads, validation).fasta_states(
+        file,
+        <(usize, B)>::default,
+        move |record, (count, buffer)| {
+            *count += 1;
+            record.copy_into(&mut owned);
+            f(&owned, buffer);
+        },
+    )?;
 
-    let byte_reader = Arc::new(Mutex::new(FastaByteReader::new(file, buffer_size)?));
-    
     let mut total = 0;
-    std::thread::scope(|scope| { 
-        let mut threads = Vec::new();
-
-        let (tx, rx) = mpsc::channel::<B>();
-
-        // Results thread
-        scope.spawn(move || {
-            let mut total_result = 0;
-            for e in rx {
-                h(e);
-            }
-            println!("Totale resultate: {}", total_result);
-        });
-
-        for _thread in 0..num_threads {
-            let mut fasta_reader = FastaReader::with_capacity(buffer_size);
-            let mut reader_clone = byte_reader.clone(); // Need to clone for move
-            let mut count = 0;
-            
-            let mut buffer: B = B::default();
-
-            let f_clone = f.clone();
-            let tx: mpsc::Sender<B> = tx.clone();
-
-            let mut record = OwnedFastaRecord::new();
-            threads.push(scope.spawn(move || {
-
-                while let Some(()) = fasta_reader
-                    .load_batch_par(&mut reader_clone)
-                    .expect("Batch is invalid")
-                {
-                    while let Some(_) = fasta_reader.next(&mut record) {
-                        count += 1;
-                        if !record.valid_extended() {
-                            panic!("Invalid record {}", record.to_string())
-                        }
-                        f_clone(&record, &mut buffer);
-
-                        if count > 10_000 {
-                            let _res = tx.send(buffer);
-                            buffer = B::default();
-                        }
-                    }
-                }
-                let _res = tx.send(buffer);
-                count
-            }));
-        }
+    for (count, buffer) in states {
+        total += count;
+        h(buffer);
+    }
+    Ok(total)
+}
 
-        for thread_guard in threads {
-            let result = thread_guard.join().unwrap();
-            total += result;
-        }
 
-    
-    });
-    Ok(total)
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::{
+        error::RecordPosition,
+        validation::{OnInvalid, Validation},
+    };
+
+    #[test]
+    fn test_invalid_base() {
+        let record = "@read\nACGT\n+\nIIII\n";
+        let input = format!("{}@read\nACXT\n+\nIIII\n{}", record.repeat(99), record.repeat(100));
+        let dna = |on_invalid| ValidationPolicy::new(Validation::Dna, on_invalid);
+
+        let count = read_fastq_par(Cursor::new(record.repeat(200)), 64, 4, dna(OnInvalid::Error), |_| 1).unwrap();
+        assert_eq!(count, 200);
+
+        let err = read_fastq_par(Cursor::new(input.clone().into_bytes()), 64, 4, dna(OnInvalid::Error), |_| 1).unwrap_err();
+        let position = RecordPosition {
+            byte_offset: 99 * record.len() as u64,
+            record_number: 100,
+        };
+        assert!(matches!(err, BioReaderError::InvalidByte { byte: b'X', position: p } if p == position), "{err}");
+
+        let skipped = Arc::new(AtomicU64::new(0));
+        let policy = dna(OnInvalid::SkipAndCount(skipped.clone()));
+        let count = read_fastq_par(Cursor::new(input.clone().into_bytes()), 64, 4, policy, |_| 1).unwrap();
+        assert_eq!((count, skipped.load(Ordering::Relaxed)), (199, 1));
+
+        let count = read_fastq_par(Cursor::new(input.into_bytes()), 64, 4, dna(OnInvalid::PassThrough), |_| 1).unwrap();
+        assert_eq!(count, 200);
+
+        // Soft-masked bases are only valid with an alphabet that includes them
+        let masked = "@read\nACgt\n+\nIIII\n".repeat(10);
+        assert!(read_fastq_par(Cursor::new(masked.clone()), 64, 2, dna(OnInvalid::Error), |_| 1).is_err());
+        let policy = ValidationPolicy::new(Validation::custom(b"ACGTNacgtn"), OnInvalid::Error);
+        assert_eq!(read_fastq_par(Cursor::new(masked), 64, 2, policy, |_| 1).unwrap(), 10);
+    }
+
+    #[test]
+    fn test_merge_results() {
+        let fastq = "@read\nACGT\n+\nIIII\n@read\nAC\n+\nII\n".repeat(100);
+        let bases: usize = read_fastq_pair_par(
+            Cursor::new(fastq.clone()),
+            Cursor::new(fastq.clone()),
+            64,
+            3,
+            ValidationPolicy::default(),
+            |record1, record2| record1.seq().len() + record2.seq().len(),
+        )
+        .unwrap();
+        assert_eq!(bases, 1200);
+
+        let mut lengths: Vec<usize> =
+            read_fastq_par(Cursor::new(fastq), 64, 3, ValidationPolicy::default(), |record| vec![record.seq().len()]).unwrap();
+        lengths.sort_unstable();
+        assert_eq!(lengths, [vec![2; 100], vec![4; 100]].concat());
+    }
 }
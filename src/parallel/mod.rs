@@ -1 +1,6 @@
-pub mod fastq;
\ No newline at end of file
+pub mod builder;
+pub mod fastq;
+pub mod pipeline;
+pub(crate) mod producer;
+pub mod progress;
+pub mod reorder;
@@ -0,0 +1,223 @@
+use std::io::{Read, Write};
+
+use crate::{
+    error::BioReaderError,
+    fastq_writer::{format_record, FastqWriter},
+    parallel::builder::{send_chunk, ParallelReaderBuilder},
+    sequence::fastq_record::{FastqRecord, LineEnding, OwnedFastqRecord, RefFastqRecord},
+};
+
+/// Records of one chunk formatted by a worker and the number of records in it
+type FormattedChunk = (Vec<u8>, u64);
+
+/// Records of one pair of chunks formatted by a worker, both buffers hold the same number of records
+type FormattedPairChunk = (Vec<u8>, Vec<u8>, u64);
+
+/// Line ending of the writer if set, otherwise the one of the input record
+#[inline]
+fn line_ending<R: FastqRecord>(fixed: Option<LineEnding>, record: &R) -> LineEnding {
+    fixed.unwrap_or_else(|| record.line_ending().unwrap_or(LineEnding::Unix))
+}
+
+/// Read, transform and write FASTQ records.
+///
+/// Workers format their output records into a buffer per chunk and the calling
+/// thread writes the buffers, in input order if [`ParallelReaderBuilder::ordered`]
+/// is set. Compression is up to the writers, e.g. [`FastqWriter::create`] with a
+/// `.gz` path. All functions return the number of written records (pairs).
+impl ParallelReaderBuilder {
+    /// Write the record returned by `f` for every input record, `None` drops the record
+    pub fn write_fastq<R, W, F>(&self, reader: R, writer: &mut FastqWriter<W>, mut f: F) -> Result<u64, BioReaderError>
+    where
+        R: Read + Send,
+        W: Write,
+        F: FnMut(&RefFastqRecord) -> Option<OwnedFastqRecord> + Clone + Send,
+    {
+        let fixed = writer.line_ending();
+        self.format_fastq(reader, writer, move |record, out| match f(record) {
+            Some(output) => {
+                format_record(&output, line_ending(fixed, record), out);
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// Write the input records for which `predicate` returns true, without copying them
+    pub fn filter_fastq<R, W, P>(&self, reader: R, writer: &mut FastqWriter<W>, mut predicate: P) -> Result<u64, BioReaderError>
+    where
+        R: Read + Send,
+        W: Write,
+        P: FnMut(&RefFastqRecord) -> bool + Clone + Send,
+    {
+        let fixed = writer.line_ending();
+        self.format_fastq(reader, writer, move |record, out| {
+            let keep = predicate(record);
+            if keep {
+                format_record(record, line_ending(fixed, record), out);
+            }
+            keep
+        })
+    }
+
+    /// Paired-end [`ParallelReaderBuilder::write_fastq`], both mates are written or dropped together
+    pub fn write_fastq_pair<R1, R2, W1, W2, F>(
+        &self,
+        reader1: R1,
+        reader2: R2,
+        writer1: &mut FastqWriter<W1>,
+        writer2: &mut FastqWriter<W2>,
+        mut f: F,
+    ) -> Result<u64, BioReaderError>
+    where
+        R1: Read + Send,
+        R2: Read + Send,
+        W1: Write,
+        W2: Write,
+        F: FnMut(&RefFastqRecord, &RefFastqRecord) -> Option<(OwnedFastqRecord, OwnedFastqRecord)> + Clone + Send,
+    {
+        let (fixed1, fixed2) = (writer1.line_ending(), writer2.line_ending());
+        self.format_fastq_pair(reader1, reader2, writer1, writer2, move |record1, record2, out1, out2| {
+            match f(record1, record2) {
+                Some((output1, output2)) => {
+                    format_record(&output1, line_ending(fixed1, record1), out1);
+                    format_record(&output2, line_ending(fixed2, record2), out2);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    /// Paired-end [`ParallelReaderBuilder::filter_fastq`], both mates are written or dropped together
+    pub fn filter_fastq_pair<R1, R2, W1, W2, P>(
+        &self,
+        reader1: R1,
+        reader2: R2,
+        writer1: &mut FastqWriter<W1>,
+        writer2: &mut FastqWriter<W2>,
+        mut predicate: P,
+    ) -> Result<u64, BioReaderError>
+    where
+        R1: Read + Send,
+        R2: Read + Send,
+        W1: Write,
+        W2: Write,
+        P: FnMut(&RefFastqRecord, &RefFastqRecord) -> bool + Clone + Send,
+    {
+        let (fixed1, fixed2) = (writer1.line_ending(), writer2.line_ending());
+        self.format_fastq_pair(reader1, reader2, writer1, writer2, move |record1, record2, out1, out2| {
+            let keep = predicate(record1, record2);
+            if keep {
+                format_record(record1, line_ending(fixed1, record1), out1);
+                format_record(record2, line_ending(fixed2, record2), out2);
+            }
+            keep
+        })
+    }
+
+    /// `format` appends the output for a record to the chunk buffer and returns whether it wrote a record
+    fn format_fastq<R, W, F>(&self, reader: R, writer: &mut FastqWriter<W>, mut format: F) -> Result<u64, BioReaderError>
+    where
+        R: Read + Send,
+        W: Write,
+        F: FnMut(&RefFastqRecord, &mut Vec<u8>) -> bool + Clone + Send,
+    {
+        self.map_chunks(
+            |(bytes, records): FormattedChunk| {
+                writer.write_chunk(&bytes)?;
+                Ok(records)
+            },
+            |builder, tx| {
+                builder.fastq_chunks(
+                    reader,
+                    FormattedChunk::default,
+                    move |record, (out, records): &mut FormattedChunk| {
+                        *records += format(record, out) as u64;
+                        Ok(())
+                    },
+                    send_chunk(tx),
+                )
+            },
+        )
+    }
+
+    fn format_fastq_pair<R1, R2, W1, W2, F>(
+        &self,
+        reader1: R1,
+        reader2: R2,
+        writer1: &mut FastqWriter<W1>,
+        writer2: &mut FastqWriter<W2>,
+        mut format: F,
+    ) -> Result<u64, BioReaderError>
+    where
+        R1: Read + Send,
+        R2: Read + Send,
+        W1: Write,
+        W2: Write,
+        F: FnMut(&RefFastqRecord, &RefFastqRecord, &mut Vec<u8>, &mut Vec<u8>) -> bool + Clone + Send,
+    {
+        self.map_chunks(
+            |(bytes1, bytes2, pairs): FormattedPairChunk| {
+                writer1.write_chunk(&bytes1)?;
+                writer2.write_chunk(&bytes2)?;
+                Ok(pairs)
+            },
+            |builder, tx| {
+                builder.fastq_pair_chunks(
+                    reader1,
+                    reader2,
+                    FormattedPairChunk::default,
+                    move |record1, record2, (out1, out2, pairs): &mut FormattedPairChunk| {
+                        *pairs += format(record1, record2, out1, out2) as u64;
+                        Ok(())
+                    },
+                    send_chunk(tx),
+                )
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_filter_pair_ordered() {
+        let fastq1: String = (0..3000).map(|i| format!("@read{}/1\nACGT\n+\nIIII\n", i)).collect();
+        let fastq2: String = (0..3000).map(|i| format!("@read{}/2\nTTGA\n+\nIIII\n", i)).collect();
+        let builder = ParallelReaderBuilder::new().num_threads(4).chunk_size(256).ordered(true);
+
+        let (mut writer1, mut writer2) = (FastqWriter::new(Vec::new()), FastqWriter::new(Vec::new()));
+        let pairs = builder
+            .filter_fastq_pair(Cursor::new(fastq1.clone()), Cursor::new(fastq2), &mut writer1, &mut writer2, |record1, _| {
+                record1.head().starts_with(b"read1")
+            })
+            .unwrap();
+        let (out1, out2) = (writer1.finish().unwrap(), writer2.finish().unwrap());
+
+        let expected1: String = (0..3000).filter(|i| i.to_string().starts_with('1')).map(|i| format!("@read{}/1\nACGT\n+\nIIII\n", i)).collect();
+        let expected2: String = (0..3000).filter(|i| i.to_string().starts_with('1')).map(|i| format!("@read{}/2\nTTGA\n+\nIIII\n", i)).collect();
+        assert_eq!(pairs, 1111);
+        assert_eq!(String::from_utf8(out1).unwrap(), expected1);
+        assert_eq!(String::from_utf8(out2).unwrap(), expected2);
+
+        // Transformed records are written in input order as well
+        let mut writer = FastqWriter::new(Vec::new());
+        let written = builder
+            .write_fastq(Cursor::new(fastq1), &mut writer, |record| {
+                Some(OwnedFastqRecord {
+                    header: record.head().to_vec(),
+                    sequence: record.seq()[..2].to_vec(),
+                    quality: record.qual()[..2].to_vec(),
+                })
+            })
+            .unwrap();
+        let expected: String = (0..3000).map(|i| format!("@read{}/1\nAC\n+\nII\n", i)).collect();
+        assert_eq!(written, 3000);
+        assert_eq!(String::from_utf8(writer.finish().unwrap()).unwrap(), expected);
+    }
+}
@@ -0,0 +1,98 @@
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::{
+    error::BioReaderError,
+    fastq_byte_reader::{Chunk, FillBuffer, FillBufferPair},
+};
+
+/// Buffers of finished chunks, handed back by the workers for the producer to refill
+pub(crate) struct BufferPool {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    capacity: usize,
+}
+
+impl BufferPool {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        Self { tx, rx, capacity }
+    }
+
+    /// A returned buffer or a new one while all buffers are in use
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.rx.try_recv().unwrap_or_else(|_| vec![0; self.capacity])
+    }
+
+    pub(crate) fn give(&self, buffer: Vec<u8>) {
+        // The pool lives as long as the run, the send cannot fail
+        let _ = self.tx.send(buffer);
+    }
+}
+
+/// Fill chunks from `reader` and send them to the workers until the input is exhausted,
+/// `stop` returns true or all workers are gone
+pub(crate) fn produce<B: FillBuffer>(
+    reader: &mut B,
+    pool: &BufferPool,
+    tx: Sender<Chunk>,
+    stop: impl Fn() -> bool,
+) -> Result<(), BioReaderError> {
+    while !stop() {
+        let mut buffer = pool.take();
+        let (position, index) = (reader.position(), reader.chunk_index());
+        let fill = match reader.fill_buf(&mut buffer)? {
+            Some(fill) if fill > 0 => fill,
+            _ => break,
+        };
+        if tx.send(Chunk { buffer, fill, position, index }).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// [`produce`] for paired input, both chunks of a pair hold the same records
+pub(crate) fn produce_pair<B: FillBufferPair>(
+    reader: &mut B,
+    pool: &BufferPool,
+    tx: Sender<(Chunk, Chunk)>,
+    stop: impl Fn() -> bool,
+) -> Result<(), BioReaderError> {
+    while !stop() {
+        let (mut buffer1, mut buffer2) = (pool.take(), pool.take());
+        let ((position1, position2), index) = (reader.positions(), reader.chunk_index());
+        let (fill1, fill2) = match reader.fill_buf(&mut buffer1, &mut buffer2)? {
+            Some((fill1, fill2)) if fill1 > 0 || fill2 > 0 => (fill1, fill2),
+            _ => break,
+        };
+        let chunk1 = Chunk { buffer: buffer1, fill: fill1, position: position1, index };
+        let chunk2 = Chunk { buffer: buffer2, fill: fill2, position: position2, index };
+        if tx.send((chunk1, chunk2)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::fastq_byte_reader::{count_fastq_records, FastqByteReader};
+
+    #[test]
+    fn test_produce() {
+        let fastq = "@read\nACGT\n+\nIIII\n".repeat(100);
+        let mut reader = FastqByteReader::new(Cursor::new(fastq), 64).unwrap();
+        let pool = BufferPool::new(64);
+        let (tx, rx) = crossbeam_channel::unbounded();
+        produce(&mut reader, &pool, tx, || false).unwrap();
+
+        let chunks: Vec<Chunk> = rx.iter().collect();
+        assert!(chunks.iter().enumerate().all(|(i, chunk)| chunk.index == i as u64));
+        assert_eq!(chunks.iter().map(|chunk| count_fastq_records(&chunk.buffer[..chunk.fill])).sum::<usize>(), 100);
+        // Record numbers count from 1
+        assert_eq!(chunks[1].position.record_number, count_fastq_records(&chunks[0].buffer[..chunks[0].fill]) as u64 + 1);
+    }
+}
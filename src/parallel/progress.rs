@@ -0,0 +1,74 @@
+use std::{
+    io::Read,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// Callback invoked by the workers after every chunk
+pub type ProgressCallback = Arc<dyn Fn(Progress) + Send + Sync>;
+
+/// Totals of a parallel run so far
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes read from the input, i.e. compressed bytes for compressed input
+    pub bytes_read: u64,
+    /// Bytes handed to the byte readers after decompression
+    pub bytes_decompressed: u64,
+    /// Records handed to the closure, without skipped invalid records
+    pub records: u64,
+    /// Finished chunks
+    pub chunks: u64,
+    /// Chunks loaded by a worker but not finished yet
+    pub chunks_in_flight: u64,
+}
+
+/// Counters of a run that can be read from another thread while it runs.
+///
+/// Counters passed to several runs add up the totals of all of them, share them with
+/// [`crate::parallel::builder::ParallelReaderBuilder::progress_counters`] through an [`Arc`].
+#[derive(Debug, Default)]
+pub struct ProgressCounters {
+    pub(crate) bytes_read: AtomicU64,
+    pub(crate) bytes_decompressed: AtomicU64,
+    pub(crate) records: AtomicU64,
+    pub(crate) chunks: AtomicU64,
+    pub(crate) chunks_in_flight: AtomicU64,
+}
+
+impl ProgressCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> Progress {
+        Progress {
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_decompressed: self.bytes_decompressed.load(Ordering::Relaxed),
+            records: self.records.load(Ordering::Relaxed),
+            chunks: self.chunks.load(Ordering::Relaxed),
+            chunks_in_flight: self.chunks_in_flight.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Reader that adds the number of bytes read to a counter
+pub(crate) struct CountingReader<'a, R> {
+    inner: R,
+    count: &'a AtomicU64,
+}
+
+impl<'a, R: Read> CountingReader<'a, R> {
+    pub(crate) fn new(inner: R, count: &'a AtomicU64) -> Self {
+        Self { inner, count }
+    }
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+
+/// Holds results of chunks that finished early until all preceding chunks are done
+#[derive(Debug)]
+pub struct ReorderBuffer<T> {
+    next: u64,
+    pending: BTreeMap<u64, T>,
+}
+
+impl<T> Default for ReorderBuffer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new() -> Self {
+        Self {
+            next: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Add the result of the chunk with sequence number `index`
+    pub fn push(&mut self, index: u64, item: T) {
+        debug_assert!(index >= self.next, "Chunk {} was already emitted", index);
+        self.pending.insert(index, item);
+    }
+
+    /// Next result in input order, `None` while it has not been pushed yet
+    pub fn pop(&mut self) -> Option<T> {
+        let item = self.pending.remove(&self.next)?;
+        self.next += 1;
+        Some(item)
+    }
+
+    /// Number of results waiting for an earlier chunk
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reorder() {
+        let mut buffer = ReorderBuffer::new();
+        buffer.push(2, 'c');
+        buffer.push(1, 'b');
+        assert_eq!(buffer.pop(), None);
+        buffer.push(0, 'a');
+        assert_eq!(std::iter::from_fn(|| buffer.pop()).collect::<String>(), "abc");
+        assert!(buffer.is_empty());
+    }
+}
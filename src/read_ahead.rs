@@ -0,0 +1,148 @@
+use std::{
+    io::{Error, Read},
+    sync::mpsc::{self, Receiver, Sender, SyncSender},
+    thread::{JoinHandle, Scope},
+};
+
+// Bytes read per block and number of blocks queued ahead of the consumer
+const BLOCK_SIZE: usize = 1 << 18;
+const QUEUE_LEN: usize = 8;
+
+type Block = Result<Vec<u8>, Error>;
+
+/// Reader that reads (and thereby decompresses) its source on a background thread.
+///
+/// Blocks are handed over through a bounded queue, so decompression of a
+/// single gzip stream overlaps with the consumer instead of stalling it.
+pub struct ReadAhead {
+    blocks: Option<Receiver<Block>>,
+    recycle: Sender<Vec<u8>>,
+    handle: Option<JoinHandle<()>>,
+    block: Vec<u8>,
+    block_pos: usize,
+}
+
+impl ReadAhead {
+    pub fn new<R: Read + Send + 'static>(mut reader: R) -> Self {
+        let (mut read_ahead, block_tx, recycle_rx) = Self::unstarted();
+        read_ahead.handle = Some(std::thread::spawn(move || Self::produce(&mut reader, block_tx, recycle_rx)));
+        read_ahead
+    }
+
+    /// Like [`ReadAhead::new`] for a reader that only lives as long as `scope`, the returned reader is `'static` nonetheless.
+    ///
+    /// The thread is joined by the scope, a panic of it surfaces there instead of in [`Read::read`].
+    pub(crate) fn scoped<'scope, R: Read + Send + 'scope>(scope: &'scope Scope<'scope, '_>, mut reader: R) -> Self {
+        let (read_ahead, block_tx, recycle_rx) = Self::unstarted();
+        scope.spawn(move || Self::produce(&mut reader, block_tx, recycle_rx));
+        read_ahead
+    }
+
+    /// Reader without a producer and the ends of the queues for it
+    fn unstarted() -> (Self, SyncSender<Block>, Receiver<Vec<u8>>) {
+        let (block_tx, block_rx) = mpsc::sync_channel(QUEUE_LEN);
+        let (recycle_tx, recycle_rx) = mpsc::channel::<Vec<u8>>();
+        let read_ahead = Self {
+            blocks: Some(block_rx),
+            recycle: recycle_tx,
+            handle: None,
+            block: Vec::new(),
+            block_pos: 0,
+        };
+        (read_ahead, block_tx, recycle_rx)
+    }
+
+    fn produce<R: Read>(reader: &mut R, blocks: SyncSender<Block>, recycle: Receiver<Vec<u8>>) {
+        loop {
+            let mut block = recycle.try_recv().unwrap_or_default();
+            block.clear();
+            match reader.by_ref().take(BLOCK_SIZE as u64).read_to_end(&mut block) {
+                Ok(0) => break,
+                Ok(_) => {
+                    // The consumer was dropped
+                    if blocks.send(Ok(block)).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    let _ = blocks.send(Err(err));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Load the next block, false at the end of the stream
+    fn next_block(&mut self) -> Result<bool, Error> {
+        let Some(blocks) = &self.blocks else {
+            return Ok(false);
+        };
+
+        match blocks.recv() {
+            Ok(block) => {
+                let old = std::mem::replace(&mut self.block, block?);
+                let _ = self.recycle.send(old);
+                self.block_pos = 0;
+                Ok(true)
+            }
+            Err(_) => {
+                // The producer finished, a panic must not look like the end of the file
+                self.blocks = None;
+                match self.handle.take().map(JoinHandle::join) {
+                    Some(Err(_)) => Err(Error::other("Read ahead thread panicked")),
+                    _ => Ok(false),
+                }
+            }
+        }
+    }
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.block_pos >= self.block.len() && !self.next_block()? {
+            return Ok(0);
+        }
+
+        let n = std::cmp::min(buf.len(), self.block.len() - self.block_pos);
+        buf[..n].copy_from_slice(&self.block[self.block_pos..self.block_pos + n]);
+        self.block_pos += n;
+        Ok(n)
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        // Closing the queue stops the producer after its current block
+        self.blocks.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{read::MultiGzDecoder, write::GzEncoder};
+
+    use super::*;
+
+    #[test]
+    fn test_read_ahead() {
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| b"ACGT\n"[(i % 5) as usize]).collect();
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut out = Vec::new();
+        ReadAhead::new(MultiGzDecoder::new(std::io::Cursor::new(compressed.clone())))
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+
+        // Dropping the reader early must not block on the producer
+        let mut reader = ReadAhead::new(MultiGzDecoder::new(std::io::Cursor::new(compressed)));
+        reader.read_exact(&mut [0; 10]).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use memchr::{memchr, memrchr};
+
+use crate::{
+    error::{BioReaderError, RecordPosition},
+    fastq_byte_reader::FillBuffer,
+    sam_header::SamHeader,
+};
+
+/// Reads a SAM file in chunks of complete alignment lines.
+///
+/// The header is parsed when the reader is created, chunks handed out by
+/// [`FillBuffer::fill_buf`] only contain alignment lines.
+pub struct SamByteReader<T>
+where
+    T: std::io::Read,
+{
+    file: T,
+    buffer_fill: usize,
+    buffer: Vec<u8>,
+    finished: bool,
+    header: SamHeader,
+    chunk_position: RecordPosition,
+    chunk_index: u64,
+}
+
+impl<T: std::io::Read> FillBuffer for SamByteReader<T> {
+    fn position(&self) -> RecordPosition {
+        self.chunk_position
+    }
+
+    fn chunk_index(&self) -> u64 {
+        self.chunk_index
+    }
+
+    fn fill_buf(&mut self, buf: &mut Vec<u8>) -> Result<Option<usize>, BioReaderError> {
+        if self.buffer_fill == 0 && self.finished {
+            return Ok(None);
+        }
+
+        // Find end of last complete line, grow the buffer if not even one line fits
+        let mut index = if self.finished {
+            self.buffer_fill
+        } else {
+            self.find_next(&self.buffer[..self.buffer_fill])
+        };
+
+        while index == 0 && !self.finished {
+            self.grow_buffer();
+            self.read_file()?;
+            index = match self.finished {
+                true => self.buffer_fill,
+                false => self.find_next(&self.buffer[..self.buffer_fill]),
+            };
+        }
+
+        if buf.len() < index {
+            buf.resize(self.buffer.len(), 0);
+        }
+
+        buf[..index].copy_from_slice(&self.buffer[..index]);
+        self.buffer.copy_within(index..self.buffer_fill, 0);
+        self.buffer_fill -= index;
+
+        let lines = memchr::memchr_iter(b'\n', &buf[..index]).count() + (buf[index - 1] != b'\n') as usize;
+        self.chunk_position.advance(index, lines);
+        self.chunk_index += 1;
+
+        self.read_file()?;
+
+        Ok(Some(index))
+    }
+}
+
+impl<T: std::io::Read> SamByteReader<T> {
+    pub fn new(reader: T, chunk_size: usize) -> Result<Self, BioReaderError> {
+        let mut br = Self {
+            file: reader,
+            buffer_fill: 0,
+            buffer: vec![0; chunk_size.max(1)],
+            finished: false,
+            header: SamHeader::default(),
+            chunk_position: RecordPosition::default(),
+            chunk_index: 0,
+        };
+        br.read_file()?;
+        br.read_header()?;
+
+        Ok(br)
+    }
+
+    pub fn header(&self) -> &SamHeader {
+        &self.header
+    }
+
+    /// Position after the last newline in the slice, 0 if there is none
+    pub fn find_next(&self, buffer_slice: &[u8]) -> usize {
+        memrchr(b'\n', buffer_slice).map_or(0, |pos| pos + 1)
+    }
+
+    fn read_header(&mut self) -> Result<(), BioReaderError> {
+        let mut text = Vec::new();
+
+        while self.buffer_fill > 0 && self.buffer[0] == b'@' {
+            let line_end = match memchr(b'\n', &self.buffer[..self.buffer_fill]) {
+                Some(pos) => pos + 1,
+                None if self.finished => self.buffer_fill,
+                None => {
+                    self.grow_buffer();
+                    self.read_file()?;
+                    continue;
+                }
+            };
+
+            text.extend_from_slice(&self.buffer[..line_end]);
+            self.chunk_position.byte_offset += line_end as u64;
+            self.buffer.copy_within(line_end..self.buffer_fill, 0);
+            self.buffer_fill -= line_end;
+            self.read_file()?;
+        }
+
+        self.header = SamHeader::parse(&text)?;
+        Ok(())
+    }
+
+    fn grow_buffer(&mut self) {
+        self.buffer.resize(self.buffer.len() * 2, 0);
+    }
+
+    /// Fill the internal buffer until it is full or the file has been read completely
+    pub fn read_file(&mut self) -> std::io::Result<usize> {
+        let mut read_bytes = 0;
+        while !self.finished && self.buffer_fill < self.buffer.len() {
+            let n_bytes = self.file.read(&mut self.buffer[self.buffer_fill..])?;
+            self.finished = n_bytes == 0;
+            self.buffer_fill += n_bytes;
+            read_bytes += n_bytes;
+        }
+        Ok(read_bytes)
+    }
+}
@@ -0,0 +1,325 @@
+use memchr::memchr_iter;
+
+use crate::{
+    error::{BioReaderError, RecordPosition},
+    sequence::fastq_record::{find_line_ending, LineEnding},
+};
+
+/// Two letter tag of a header field, e.g. `SN` or `LN`
+pub type HeaderTag = [u8; 2];
+
+/// `TAG:VALUE` fields of a header line in the order they appeared in the file
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HeaderFields {
+    fields: Vec<(HeaderTag, Vec<u8>)>,
+}
+
+impl HeaderFields {
+    /// Parse the tab separated fields following the record type, e.g. `SN:chr1\tLN:1000`,
+    /// `position` is the position of the line and only used for errors
+    pub fn parse(line: &[u8], position: RecordPosition) -> Result<Self, BioReaderError> {
+        let mut fields = Vec::new();
+        for field in line.split(|&c| c == b'\t') {
+            if field.len() < 3 || field[2] != b':' {
+                return Err(invalid(
+                    format!("Invalid SAM header field '{}'", String::from_utf8_lossy(field)),
+                    position,
+                ));
+            }
+            fields.push(([field[0], field[1]], field[3..].to_vec()));
+        }
+        Ok(Self { fields })
+    }
+
+    #[inline]
+    pub fn get(&self, tag: &HeaderTag) -> Option<&[u8]> {
+        self.fields
+            .iter()
+            .find(|(t, _)| t == tag)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Replace the value of a tag or append it if the tag is not present
+    pub fn set(&mut self, tag: HeaderTag, value: Vec<u8>) {
+        match self.fields.iter_mut().find(|(t, _)| *t == tag) {
+            Some((_, v)) => *v = value,
+            None => self.fields.push((tag, value)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&HeaderTag, &[u8])> {
+        self.fields.iter().map(|(tag, value)| (tag, value.as_slice()))
+    }
+
+    fn require(&self, tag: &HeaderTag, kind: &str, position: RecordPosition) -> Result<&[u8], BioReaderError> {
+        self.get(tag).ok_or_else(|| {
+            invalid(
+                format!("SAM header line @{} is missing required tag {}", kind, String::from_utf8_lossy(tag)),
+                position,
+            )
+        })
+    }
+}
+
+/// `@HD` line
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HeaderLine {
+    pub fields: HeaderFields,
+}
+
+impl HeaderLine {
+    pub fn version(&self) -> Option<&[u8]> {
+        self.fields.get(b"VN")
+    }
+
+    pub fn sort_order(&self) -> Option<&[u8]> {
+        self.fields.get(b"SO")
+    }
+
+    pub fn group_order(&self) -> Option<&[u8]> {
+        self.fields.get(b"GO")
+    }
+}
+
+/// `@SQ` line
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReferenceSequence {
+    pub fields: HeaderFields,
+}
+
+impl ReferenceSequence {
+    pub fn name(&self) -> &[u8] {
+        self.fields.get(b"SN").unwrap_or_default()
+    }
+
+    pub fn length(&self) -> u64 {
+        self.fields.get(b"LN").and_then(parse_u64).unwrap_or_default()
+    }
+}
+
+/// `@RG` line
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReadGroup {
+    pub fields: HeaderFields,
+}
+
+impl ReadGroup {
+    pub fn id(&self) -> &[u8] {
+        self.fields.get(b"ID").unwrap_or_default()
+    }
+
+    pub fn sample(&self) -> Option<&[u8]> {
+        self.fields.get(b"SM")
+    }
+
+    pub fn library(&self) -> Option<&[u8]> {
+        self.fields.get(b"LB")
+    }
+
+    pub fn platform(&self) -> Option<&[u8]> {
+        self.fields.get(b"PL")
+    }
+}
+
+/// `@PG` line
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Program {
+    pub fields: HeaderFields,
+}
+
+impl Program {
+    pub fn id(&self) -> &[u8] {
+        self.fields.get(b"ID").unwrap_or_default()
+    }
+
+    pub fn name(&self) -> Option<&[u8]> {
+        self.fields.get(b"PN")
+    }
+
+    pub fn version(&self) -> Option<&[u8]> {
+        self.fields.get(b"VN")
+    }
+
+    pub fn command_line(&self) -> Option<&[u8]> {
+        self.fields.get(b"CL")
+    }
+
+    pub fn previous(&self) -> Option<&[u8]> {
+        self.fields.get(b"PP")
+    }
+}
+
+/// A line of a [`SamHeader`], see [`SamHeader::records`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderRecord<'a> {
+    Header(&'a HeaderLine),
+    Reference(&'a ReferenceSequence),
+    ReadGroup(&'a ReadGroup),
+    Program(&'a Program),
+    Comment(&'a [u8]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordKind {
+    Header,
+    Reference,
+    ReadGroup,
+    Program,
+    Comment,
+}
+
+/// Parsed SAM header
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SamHeader {
+    pub header: Option<HeaderLine>,
+    pub references: Vec<ReferenceSequence>,
+    pub read_groups: Vec<ReadGroup>,
+    pub programs: Vec<Program>,
+    /// Content of `@CO` lines
+    pub comments: Vec<Vec<u8>>,
+    // Record types of the parsed lines in file order
+    order: Vec<RecordKind>,
+    line_ending: Option<LineEnding>,
+}
+
+impl SamHeader {
+    /// Parse the header lines at the start of a SAM file, every line has to start with '@'
+    pub fn parse(text: &[u8]) -> Result<Self, BioReaderError> {
+        Self::parse_at(text, RecordPosition::default())
+    }
+
+    /// [`SamHeader::parse`] for a header text starting at `position` of the input
+    pub(crate) fn parse_at(text: &[u8], mut position: RecordPosition) -> Result<Self, BioReaderError> {
+        let mut header = SamHeader {
+            line_ending: find_line_ending(text),
+            ..Default::default()
+        };
+
+        let mut start = 0;
+        let ends = memchr_iter(b'\n', text).chain(std::iter::once(text.len()));
+        for end in ends {
+            let line = trim_cr(&text[start..end]);
+            if !line.is_empty() {
+                header.parse_line(line, position)?;
+            }
+            position.advance(end + 1 - start, 1);
+            start = end + 1;
+        }
+
+        Ok(header)
+    }
+
+    fn parse_line(&mut self, line: &[u8], position: RecordPosition) -> Result<(), BioReaderError> {
+        // The record type is followed by a tab unless the line ends after it
+        if line.len() < 3 || line[0] != b'@' || line.get(3).is_some_and(|&c| c != b'\t') {
+            return Err(invalid(
+                format!("Invalid SAM header line '{}'", String::from_utf8_lossy(line)),
+                position,
+            ));
+        }
+
+        let kind = &line[1..3];
+        let content = line.get(4..).unwrap_or_default();
+
+        let record_kind = match kind {
+            b"HD" => {
+                let fields = HeaderFields::parse(content, position)?;
+                fields.require(b"VN", "HD", position)?;
+                // A repeated @HD line replaces the first one
+                self.order.retain(|kind| *kind != RecordKind::Header);
+                self.header = Some(HeaderLine { fields });
+                RecordKind::Header
+            }
+            b"SQ" => {
+                let fields = HeaderFields::parse(content, position)?;
+                fields.require(b"SN", "SQ", position)?;
+                if parse_u64(fields.require(b"LN", "SQ", position)?).is_none() {
+                    return Err(invalid("SAM header line @SQ has an invalid LN".to_string(), position));
+                }
+                self.references.push(ReferenceSequence { fields });
+                RecordKind::Reference
+            }
+            b"RG" => {
+                let fields = HeaderFields::parse(content, position)?;
+                fields.require(b"ID", "RG", position)?;
+                self.read_groups.push(ReadGroup { fields });
+                RecordKind::ReadGroup
+            }
+            b"PG" => {
+                let fields = HeaderFields::parse(content, position)?;
+                fields.require(b"ID", "PG", position)?;
+                self.programs.push(Program { fields });
+                RecordKind::Program
+            }
+            b"CO" => {
+                self.comments.push(content.to_vec());
+                RecordKind::Comment
+            }
+            _ => {
+                return Err(invalid(
+                    format!("Unknown SAM header record type @{}", String::from_utf8_lossy(kind)),
+                    position,
+                ))
+            }
+        };
+        self.order.push(record_kind);
+        Ok(())
+    }
+
+    /// All lines in the order they were parsed.
+    ///
+    /// Lines added to the public fields afterwards follow grouped as @HD, @SQ, @RG, @PG, @CO.
+    pub fn records(&self) -> impl Iterator<Item = HeaderRecord<'_>> {
+        let mut header = self.header.iter().map(HeaderRecord::Header);
+        let mut references = self.references.iter().map(HeaderRecord::Reference);
+        let mut read_groups = self.read_groups.iter().map(HeaderRecord::ReadGroup);
+        let mut programs = self.programs.iter().map(HeaderRecord::Program);
+        let mut comments = self.comments.iter().map(|comment| HeaderRecord::Comment(comment));
+        let mut order = self.order.iter();
+
+        std::iter::from_fn(move || {
+            // Lines removed from the fields since parsing are skipped
+            for kind in order.by_ref() {
+                let record = match kind {
+                    RecordKind::Header => header.next(),
+                    RecordKind::Reference => references.next(),
+                    RecordKind::ReadGroup => read_groups.next(),
+                    RecordKind::Program => programs.next(),
+                    RecordKind::Comment => comments.next(),
+                };
+                if record.is_some() {
+                    return record;
+                }
+            }
+            header
+                .next()
+                .or_else(|| references.next())
+                .or_else(|| read_groups.next())
+                .or_else(|| programs.next())
+                .or_else(|| comments.next())
+        })
+    }
+
+    /// Line ending of the parsed header text, `None` if it had no line break
+    pub fn line_ending(&self) -> Option<LineEnding> {
+        self.line_ending
+    }
+
+    /// Index of a reference sequence by name, as used by BAM records
+    pub fn reference_index(&self, name: &[u8]) -> Option<usize> {
+        self.references.iter().position(|r| r.name() == name)
+    }
+}
+
+#[inline]
+fn trim_cr(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+fn parse_u64(value: &[u8]) -> Option<u64> {
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+fn invalid(reason: String, position: RecordPosition) -> BioReaderError {
+    BioReaderError::InvalidHeader { reason, position }
+}
@@ -0,0 +1,282 @@
+use memchr::{memchr, memchr_iter};
+
+use crate::{
+    error::{BioReaderError, RecordPosition},
+    fastq_byte_reader::{Chunk, FillBuffer},
+    sequence::sam_record::{RefSamRecord, SamPosition, MANDATORY_FIELDS},
+};
+
+/// Reads SAM alignment lines from chunks produced by a [`crate::sam_byte_reader::SamByteReader`]
+#[derive(Debug, Clone)]
+pub struct SamReader {
+    pub buffer: Vec<u8>,
+    pub buffer_pos: usize,
+    pub buffer_fill: usize,
+    sam_pos: SamPosition,
+    chunk_position: RecordPosition,
+    chunk_index: u64,
+    // Lines of the chunk started so far, empty lines included
+    line_index: u64,
+}
+
+impl SamReader {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffer: vec![0; capacity],
+            buffer_pos: 0,
+            buffer_fill: 0,
+            sam_pos: SamPosition::default(),
+            chunk_position: RecordPosition::default(),
+            chunk_index: 0,
+            line_index: 0,
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::new(capacity)
+    }
+
+    #[inline]
+    pub fn load_batch(&mut self, br: &mut impl FillBuffer) -> Result<Option<()>, BioReaderError> {
+        self.chunk_position = br.position();
+        self.chunk_index = br.chunk_index();
+        self.line_index = 0;
+        self.buffer_pos = 0;
+        self.buffer_fill = br.fill_buf(&mut self.buffer)?.unwrap_or_default();
+
+        match self.buffer_fill {
+            0 => Ok(None),
+            _ => Ok(Some(())),
+        }
+    }
+
+    /// Take over a chunk filled by another thread, returns the previous buffer for reuse
+    pub fn load_chunk(&mut self, chunk: Chunk) -> Vec<u8> {
+        self.chunk_position = chunk.position;
+        self.chunk_index = chunk.index;
+        self.line_index = 0;
+        self.buffer_pos = 0;
+        self.buffer_fill = chunk.fill;
+        std::mem::replace(&mut self.buffer, chunk.buffer)
+    }
+
+    /// Position of the record last returned
+    pub fn position(&self) -> RecordPosition {
+        self.chunk_position.in_chunk(self.sam_pos.pos.0, self.line_index.saturating_sub(1))
+    }
+
+    /// Sequence number of the loaded chunk in its input
+    pub fn chunk_index(&self) -> u64 {
+        self.chunk_index
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<RefSamRecord<'_>>, BioReaderError> {
+        // Skip empty lines, e.g. a trailing newline
+        while self.buffer_pos < self.buffer_fill && self.buffer[self.buffer_pos] == b'\n' {
+            self.buffer_pos += 1;
+            self.line_index += 1;
+        }
+
+        if self.buffer_pos >= self.buffer_fill {
+            return Ok(None);
+        }
+
+        let start = self.buffer_pos;
+        let newline = memchr(b'\n', &self.buffer[start..self.buffer_fill])
+            .map_or(self.buffer_fill, |pos| start + pos);
+        self.buffer_pos = newline + 1;
+
+        let mut end = newline;
+        self.sam_pos.crlf = end > start && self.buffer[end - 1] == b'\r';
+        if self.sam_pos.crlf {
+            end -= 1;
+        }
+
+        self.sam_pos.pos = (start, end);
+        self.line_index += 1;
+        let position = self.position();
+        self.sam_pos.record = position;
+        let line = &self.buffer[start..end];
+
+        let mut tabs = memchr_iter(b'\t', line).map(|pos| start + pos);
+        for i in 0..MANDATORY_FIELDS - 1 {
+            self.sam_pos.ends[i] = tabs.next().ok_or_else(|| {
+                invalid_line(line, "fewer than 11 mandatory fields", position)
+            })?;
+        }
+        self.sam_pos.ends[MANDATORY_FIELDS - 1] = tabs.next().unwrap_or(end);
+
+        let buffer = &self.buffer;
+        let sam_pos = &mut self.sam_pos;
+        sam_pos.flag = parse_field(buffer, sam_pos, 1, line, "FLAG", position)?;
+        sam_pos.position = parse_field(buffer, sam_pos, 3, line, "POS", position)?;
+        sam_pos.mapq = parse_field(buffer, sam_pos, 4, line, "MAPQ", position)?;
+        sam_pos.next_position = parse_field(buffer, sam_pos, 7, line, "PNEXT", position)?;
+        sam_pos.template_length = parse_field(buffer, sam_pos, 8, line, "TLEN", position)?;
+
+        Ok(Some(RefSamRecord {
+            buffer: &self.buffer,
+            sam_pos: &self.sam_pos,
+        }))
+    }
+}
+
+#[inline]
+fn parse_field<N: std::str::FromStr>(
+    buffer: &[u8],
+    sam_pos: &SamPosition,
+    index: usize,
+    line: &[u8],
+    name: &str,
+    position: RecordPosition,
+) -> Result<N, BioReaderError> {
+    std::str::from_utf8(sam_pos.field(buffer, index))
+        .ok()
+        .and_then(|field| field.parse().ok())
+        .ok_or_else(|| invalid_line(line, &format!("invalid {}", name), position))
+}
+
+fn invalid_line(line: &[u8], reason: &str, position: RecordPosition) -> BioReaderError {
+    BioReaderError::InvalidRecord {
+        reason: format!("Invalid SAM line ({}): {}", reason, String::from_utf8_lossy(line)),
+        position,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parallel::producer::{produce, BufferPool},
+        sam_byte_reader::SamByteReader,
+        sam_header::SamHeader,
+    };
+
+    const SAM: &str = "@HD\tVN:1.6\tSO:coordinate\n\
+        @SQ\tSN:chr1\tLN:1000\n\
+        @SQ\tSN:chr2\tLN:2000\n\
+        @RG\tID:rg1\tSM:sample1\n\
+        @PG\tID:bwa\tPN:bwa\tVN:0.7.17\n\
+        @CO\tfree text\n\
+        r001\t99\tchr1\t7\t30\t8M2I4M1D3M\t=\t37\t39\tTTAGATAAAGGATACTG\t*\tNM:i:1\tRG:Z:rg1\n\
+        r002\t0\tchr1\t9\t30\t3S6M1P1I4M\t*\t0\t0\tAAAAGATAAGGATA\t*\n\
+        r003\t4\t*\t0\t0\t*\t*\t0\t0\tGCCTAAGCTAA\tIIIIIIIIIII\tSA:Z:ref,29,-,6H5M,17,0;\n\
+        r004\t147\tchr2\t37\t30\t9M\t=\t7\t-39\tCAGCGGCAT\t*\tNM:i:1";
+
+    #[test]
+    fn test_header() {
+        let br = SamByteReader::new(SAM.as_bytes(), 64).unwrap();
+        let header = br.header();
+
+        assert_eq!(header.header.as_ref().unwrap().version(), Some(&b"1.6"[..]));
+        assert_eq!(header.header.as_ref().unwrap().sort_order(), Some(&b"coordinate"[..]));
+        assert_eq!(header.references.len(), 2);
+        assert_eq!(header.references[1].name(), b"chr2");
+        assert_eq!(header.references[1].length(), 2000);
+        assert_eq!(header.read_groups[0].id(), b"rg1");
+        assert_eq!(header.read_groups[0].sample(), Some(&b"sample1"[..]));
+        assert_eq!(header.programs[0].version(), Some(&b"0.7.17"[..]));
+        assert_eq!(header.comments, vec![b"free text".to_vec()]);
+        assert_eq!(header.reference_index(b"chr2"), Some(1));
+
+        let err = SamHeader::parse(b"@HD\tVN:1.6\n@SQ\tSN:chr1\n").unwrap_err();
+        assert!(matches!(err, BioReaderError::InvalidHeader { position: RecordPosition { byte_offset: 11, record_number: 2 }, .. }), "{err}");
+
+        // The record type must be followed by a tab
+        for text in [&b"@SQXSN:chr1\tLN:1\n"[..], b"@HDVN:1.6\n", b"@HD\tVN:1.6\n@CO comment\n"] {
+            assert!(matches!(SamHeader::parse(text), Err(BioReaderError::InvalidHeader { .. })));
+        }
+        assert_eq!(SamHeader::parse(b"@CO\n@CO\tfree text\n").unwrap().comments, vec![b"".to_vec(), b"free text".to_vec()]);
+    }
+
+    #[test]
+    fn test_records() {
+        // Small chunks force lines to be split over several batches
+        let mut br = SamByteReader::new(SAM.as_bytes(), 16).unwrap();
+        let mut reader = SamReader::with_capacity(16);
+
+        let mut names = Vec::new();
+        while let Some(()) = reader.load_batch(&mut br).unwrap() {
+            while let Some(record) = reader.next().unwrap() {
+                names.push(record.qname().to_vec());
+                match record.qname() {
+                    b"r001" => {
+                        assert_eq!(record.flag(), 99);
+                        assert_eq!(record.rname(), b"chr1");
+                        assert_eq!(record.pos(), 7);
+                        assert_eq!(record.mapq(), 30);
+                        assert_eq!(record.cigar(), b"8M2I4M1D3M");
+                        assert_eq!(record.rnext(), b"=");
+                        assert_eq!(record.pnext(), 37);
+                        assert_eq!(record.tlen(), 39);
+                        assert_eq!(record.seq(), b"TTAGATAAAGGATACTG");
+                        assert_eq!(record.qual(), b"*");
+                        assert_eq!(record.tags().count(), 2);
+                        assert_eq!(record.tag(b"RG"), Some((b'Z', &b"rg1"[..])));
+                    }
+                    b"r002" => assert_eq!(record.raw_tags(), b""),
+                    b"r003" => {
+                        assert!(record.is_unmapped());
+                        assert_eq!(record.qual(), b"IIIIIIIIIII");
+                    }
+                    b"r004" => {
+                        assert_eq!(record.tlen(), -39);
+                        assert_eq!(record.tag(b"NM"), Some((b'i', &b"1"[..])));
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+        assert_eq!(names, vec![b"r001", b"r002", b"r003", b"r004"]);
+    }
+
+    #[test]
+    fn test_load_chunk() {
+        // Chunks are filled by a producer thread and handed over through a channel
+        let mut br = SamByteReader::new(SAM.as_bytes(), 16).unwrap();
+        let pool = BufferPool::new(16);
+        let (tx, rx) = crossbeam_channel::unbounded();
+        produce(&mut br, &pool, tx, || false).unwrap();
+
+        let mut reader = SamReader::with_capacity(0);
+        let mut records = Vec::new();
+        for (i, chunk) in rx.into_iter().enumerate() {
+            pool.give(reader.load_chunk(chunk));
+            assert_eq!(reader.chunk_index(), i as u64);
+            while let Some(record) = reader.next().unwrap() {
+                records.push((record.qname().to_vec(), record.position().record_number));
+            }
+        }
+        let names: Vec<_> = records.iter().map(|(name, _)| name.as_slice()).collect();
+        assert_eq!(names, [b"r001", b"r002", b"r003", b"r004"]);
+        assert!(records.iter().map(|(_, number)| *number).eq(1..=4));
+    }
+
+    #[test]
+    fn test_invalid_record() {
+        let mut br = SamByteReader::new(&b"r001\t99\tchr1\n"[..], 64).unwrap();
+        let mut reader = SamReader::with_capacity(64);
+        reader.load_batch(&mut br).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Err(BioReaderError::InvalidRecord { position: RecordPosition { byte_offset: 0, record_number: 1 }, .. })
+        ));
+
+        // Positions count the alignment lines after the header
+        let sam = format!("{SAM}\nr005\t0\tchr1\tx\t0\t*\t*\t0\t0\t*\t*\n");
+        let mut br = SamByteReader::new(sam.as_bytes(), 16).unwrap();
+        let mut reader = SamReader::with_capacity(16);
+        let err = 'read: loop {
+            reader.load_batch(&mut br).unwrap().unwrap();
+            loop {
+                match reader.next() {
+                    Ok(Some(_)) => {}
+                    Ok(None) => break,
+                    Err(err) => break 'read err,
+                }
+            }
+        };
+        assert_eq!(err.position(), Some(RecordPosition { byte_offset: SAM.len() as u64 + 1, record_number: 5 }));
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Error, Write},
+    path::Path,
+};
+
+use crate::{
+    compression::{Compression, Encoder},
+    sam_header::{HeaderFields, HeaderRecord, SamHeader},
+    sequence::{
+        fastq_record::LineEnding,
+        sam_record::{OwnedSamRecord, RefSamRecord},
+    },
+};
+
+const DEFAULT_CAPACITY: usize = 1 << 20;
+
+fn eol(line_ending: LineEnding) -> &'static [u8] {
+    match line_ending {
+        LineEnding::Unix => b"\n",
+        LineEnding::Windows => b"\r\n",
+    }
+}
+
+fn format_header_line(kind: &[u8], fields: &HeaderFields, out: &mut Vec<u8>) {
+    out.push(b'@');
+    out.extend_from_slice(kind);
+    for (tag, value) in fields.iter() {
+        out.push(b'\t');
+        out.extend_from_slice(tag);
+        out.push(b':');
+        out.extend_from_slice(value);
+    }
+}
+
+/// Append the header lines in the order they were parsed, see [`SamHeader::records`]
+pub fn format_header(header: &SamHeader, line_ending: LineEnding, out: &mut Vec<u8>) {
+    for record in header.records() {
+        match record {
+            HeaderRecord::Header(hd) => format_header_line(b"HD", &hd.fields, out),
+            HeaderRecord::Reference(reference) => format_header_line(b"SQ", &reference.fields, out),
+            HeaderRecord::ReadGroup(read_group) => format_header_line(b"RG", &read_group.fields, out),
+            HeaderRecord::Program(program) => format_header_line(b"PG", &program.fields, out),
+            HeaderRecord::Comment(comment) => {
+                out.extend_from_slice(b"@CO\t");
+                out.extend_from_slice(comment);
+            }
+        }
+        out.extend_from_slice(eol(line_ending));
+    }
+}
+
+/// Append an alignment line, empty text fields are written as `*`
+pub fn format_record(record: &OwnedSamRecord, line_ending: LineEnding, out: &mut Vec<u8>) {
+    fn text(field: &[u8], out: &mut Vec<u8>) {
+        match field.is_empty() {
+            true => out.push(b'*'),
+            false => out.extend_from_slice(field),
+        }
+        out.push(b'\t');
+    }
+    fn number(field: impl ToString, out: &mut Vec<u8>) {
+        out.extend_from_slice(field.to_string().as_bytes());
+        out.push(b'\t');
+    }
+
+    text(&record.qname, out);
+    number(record.flag, out);
+    text(&record.rname, out);
+    number(record.pos, out);
+    number(record.mapq, out);
+    text(&record.cigar, out);
+    text(&record.rnext, out);
+    number(record.pnext, out);
+    number(record.tlen, out);
+    text(&record.seq, out);
+    text(&record.qual, out);
+
+    for tag in &record.tags {
+        tag.write_to(out);
+        out.push(b'\t');
+    }
+    // Replace the last tab with the line ending
+    out.pop();
+    out.extend_from_slice(eol(line_ending));
+}
+
+/// Buffered SAM writer with optional compression.
+///
+/// Borrowed records are written byte for byte as they were read, owned
+/// records are formatted from their fields. Unless set explicitly, the line
+/// ending is taken from the header or the first borrowed record and falls
+/// back to Unix otherwise.
+pub struct SamWriter<W: Write> {
+    writer: BufWriter<Encoder<W>>,
+    line_ending: Option<LineEnding>,
+    record: Vec<u8>,
+}
+
+impl SamWriter<File> {
+    /// Create a file, compressed according to its extension (`.gz`, `.bgz`, `.zst`)
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let compression = Compression::from_path(&path);
+        Self::with_compression(File::create(path)?, compression)
+    }
+}
+
+impl<W: Write> SamWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_compression(writer, Compression::None).expect("Uncompressed writer cannot fail")
+    }
+
+    pub fn with_compression(writer: W, compression: Compression) -> Result<Self, Error> {
+        Ok(Self {
+            writer: BufWriter::with_capacity(DEFAULT_CAPACITY, compression.encoder(writer)?),
+            line_ending: None,
+            record: Vec::new(),
+        })
+    }
+
+    pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = Some(line_ending);
+        self
+    }
+
+    #[inline]
+    pub fn line_ending(&self) -> Option<LineEnding> {
+        self.line_ending
+    }
+
+    pub fn write_header(&mut self, header: &SamHeader) -> Result<(), Error> {
+        let line_ending = *self
+            .line_ending
+            .get_or_insert_with(|| header.line_ending().unwrap_or(LineEnding::Unix));
+
+        self.record.clear();
+        format_header(header, line_ending, &mut self.record);
+        self.writer.write_all(&self.record)
+    }
+
+    #[inline]
+    pub fn write(&mut self, record: &OwnedSamRecord) -> Result<(), Error> {
+        let line_ending = *self.line_ending.get_or_insert(LineEnding::Unix);
+
+        self.record.clear();
+        format_record(record, line_ending, &mut self.record);
+        self.writer.write_all(&self.record)
+    }
+
+    #[inline]
+    pub fn write_ref(&mut self, record: &RefSamRecord) -> Result<(), Error> {
+        let line_ending = *self.line_ending.get_or_insert_with(|| record.line_ending());
+
+        self.writer.write_all(record.line())?;
+        self.writer.write_all(eol(line_ending))
+    }
+
+    /// Write records that were already formatted with [`format_record`]
+    #[inline]
+    pub fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        self.writer.write_all(chunk)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+
+    /// Flush all buffered records, finish the compressed stream and return the underlying writer
+    pub fn finish(self) -> Result<W, Error> {
+        self.writer.into_inner().map_err(|err| err.into_error())?.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::{BioReaderError, RecordPosition},
+        sam_byte_reader::SamByteReader,
+        sam_reader::SamReader,
+        sequence::sam_record::{AlignmentRecord, SamTag, TagArray, TagValue},
+    };
+
+    const SAM: &str = "@HD\tVN:1.6\tSO:coordinate\n\
+        @SQ\tSN:chr1\tLN:1000\n\
+        @RG\tID:rg1\tSM:sample1\n\
+        @PG\tID:bwa\tPN:bwa\n\
+        @CO\tfree text\n\
+        r001\t99\tchr1\t7\t30\t8M2I4M1D3M\t=\t37\t39\tTTAGATAAAGGATACTG\t*\tNM:i:1\tXA:A:x\tZB:B:c,-1,2\n\
+        r002\t0\tchr1\t9\t30\t3S6M1P1I4M\t*\t0\t0\tAAAAGATAAGGATA\t*\n\
+        r003\t4\t*\t0\t0\t*\t*\t0\t0\tGCCTAAGCTAA\tIIIIIIIIIII\tXH:H:1AE3\tXE:B:S\n";
+
+    fn roundtrip(sam: &str, owned: bool) -> Vec<u8> {
+        let mut br = SamByteReader::new(sam.as_bytes(), 32).unwrap();
+        let mut reader = SamReader::with_capacity(32);
+        let mut writer = SamWriter::new(Vec::new());
+        writer.write_header(br.header()).unwrap();
+
+        while let Some(()) = reader.load_batch(&mut br).unwrap() {
+            while let Some(record) = reader.next().unwrap() {
+                match owned {
+                    true => writer.write(&record.to_owned_record().unwrap()).unwrap(),
+                    false => writer.write_ref(&record).unwrap(),
+                }
+            }
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        assert_eq!(String::from_utf8(roundtrip(SAM, false)).unwrap(), SAM);
+        assert_eq!(String::from_utf8(roundtrip(SAM, true)).unwrap(), SAM);
+
+        // Comments and programs between other lines stay in place
+        let interleaved = "@HD\tVN:1.6\n\
+            @SQ\tSN:chr1\tLN:1000\n\
+            @PG\tID:bwa\tPN:bwa\n\
+            @CO\tmarked duplicates\n\
+            @PG\tID:picard\tPN:picard\tPP:bwa\n\
+            @RG\tID:rg1\tSM:sample1\n\
+            r002\t0\tchr1\t9\t30\t3S6M1P1I4M\t*\t0\t0\tAAAAGATAAGGATA\t*\n";
+        assert_eq!(String::from_utf8(roundtrip(interleaved, false)).unwrap(), interleaved);
+
+        // Lines added after parsing follow the parsed ones
+        let mut header = SamHeader::parse(&interleaved.as_bytes()[..interleaved.find("r002").unwrap()]).unwrap();
+        header.comments.push(b"added".to_vec());
+        let mut out = Vec::new();
+        format_header(&header, LineEnding::Unix, &mut out);
+        assert!(String::from_utf8(out).unwrap().ends_with("@RG\tID:rg1\tSM:sample1\n@CO\tadded\n"));
+    }
+
+    #[test]
+    fn test_windows_line_endings() {
+        let sam = SAM.replace('\n', "\r\n");
+        assert_eq!(String::from_utf8(roundtrip(&sam, false)).unwrap(), sam);
+        assert_eq!(String::from_utf8(roundtrip(&sam, true)).unwrap(), sam);
+
+        // An explicit line ending wins over the one of the input
+        let mut writer = SamWriter::new(Vec::new()).with_line_ending(LineEnding::Unix);
+        writer.write_header(&SamHeader::parse(&sam.as_bytes()[..sam.find("r001").unwrap()]).unwrap()).unwrap();
+        assert_eq!(writer.finish().unwrap(), b"@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:1000\n@RG\tID:rg1\tSM:sample1\n@PG\tID:bwa\tPN:bwa\n@CO\tfree text\n");
+    }
+
+    #[test]
+    fn test_edited_record() {
+        let mut record = OwnedSamRecord {
+            qname: b"r001".to_vec(),
+            flag: 16,
+            rname: b"chr1".to_vec(),
+            pos: 100,
+            mapq: 60,
+            cigar: b"4M".to_vec(),
+            seq: b"ACGT".to_vec(),
+            qual: b"IIII".to_vec(),
+            ..Default::default()
+        };
+        record.set_tag(*b"NM", TagValue::Int(0));
+        record.set_tag(*b"AS", TagValue::Float(-1.5));
+        record.set_tag(*b"RG", TagValue::String(b"rg1".to_vec()));
+        record.set_tag(*b"ZB", TagValue::Array(TagArray::Float(vec![0.5, 2.0])));
+        record.set_tag(*b"NM", TagValue::Int(2));
+
+        let mut out = Vec::new();
+        format_record(&record, LineEnding::Unix, &mut out);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "r001\t16\tchr1\t100\t60\t4M\t*\t0\t0\tACGT\tIIII\tNM:i:2\tAS:f:-1.5\tRG:Z:rg1\tZB:B:f,0.5,2\n"
+        );
+
+        assert_eq!(record.remove_tag(b"AS"), Some(TagValue::Float(-1.5)));
+        assert_eq!(record.tag(b"AS"), None);
+    }
+
+    #[test]
+    fn test_tag_parse() {
+        assert_eq!(SamTag::parse(b"NM:i:-3").unwrap().value, TagValue::Int(-3));
+        assert_eq!(SamTag::parse(b"XB:B:C").unwrap().value, TagValue::Array(TagArray::UInt8(vec![])));
+        assert!(SamTag::parse(b"NM:i:x").is_err());
+        assert!(SamTag::parse(b"XB:B:c1,2").is_err());
+        assert!(SamTag::parse(b"XB:B:c,300").is_err());
+        assert!(SamTag::parse(b"XH:H:1G").is_err());
+
+        // Tags of a record are reported with its position
+        let sam = "r001\t0\t*\t0\t0\t*\t*\t0\t0\t*\t*\nr002\t0\t*\t0\t0\t*\t*\t0\t0\t*\t*\tNM:i:x\n";
+        let mut br = SamByteReader::new(sam.as_bytes(), 64).unwrap();
+        let mut reader = SamReader::with_capacity(64);
+        reader.load_batch(&mut br).unwrap();
+        reader.next().unwrap();
+        let err = reader.next().unwrap().unwrap().sam_tags().unwrap_err();
+        assert!(matches!(err, BioReaderError::InvalidRecord { position: RecordPosition { byte_offset: 25, record_number: 2 }, .. }), "{err}");
+    }
+}
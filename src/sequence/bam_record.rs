@@ -0,0 +1,279 @@
+use std::borrow::Cow;
+
+use crate::{
+    error::{BioReaderError, RecordPosition},
+    sam_header::SamHeader,
+};
+
+use super::sam_record::{AlignmentRecord, SamTag, TagArray, TagValue};
+
+// Size of the fixed fields preceding the read name
+const FIXED_SIZE: usize = 32;
+
+const CIGAR_OPS: &[u8; 9] = b"MIDNSHP=X";
+const BASES: &[u8; 16] = b"=ACMGRSVTWYHKDBN";
+
+/// Single CIGAR operation, e.g. `(b'M', 10)` for `10M`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CigarOp {
+    pub op: u8,
+    pub len: u32,
+}
+
+/// A binary BAM alignment that borrows data from a buffer
+#[derive(Debug, Clone)]
+pub struct RefBamRecord<'a> {
+    // Record without the leading block_size
+    data: &'a [u8],
+    position: RecordPosition,
+}
+
+#[inline]
+fn i32_at(data: &[u8], pos: usize) -> i32 {
+    i32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+#[inline]
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn invalid(reason: &str, position: RecordPosition) -> BioReaderError {
+    BioReaderError::InvalidRecord {
+        reason: reason.to_string(),
+        position,
+    }
+}
+
+impl<'a> RefBamRecord<'a> {
+    /// Check that all variable length fields lie within the record, `position` is reported by errors
+    pub fn new(data: &'a [u8], position: RecordPosition) -> Result<Self, BioReaderError> {
+        if data.len() < FIXED_SIZE {
+            return Err(invalid("BAM record is shorter than its fixed fields", position));
+        }
+        let record = Self { data, position };
+        if record.qual_start() + record.seq_len() > data.len() {
+            return Err(invalid("BAM record is shorter than its fields", position));
+        }
+        if record.read_name_len() == 0 {
+            return Err(invalid("BAM record without read name", position));
+        }
+        Ok(record)
+    }
+
+    #[inline]
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Position of the record in the decompressed input
+    #[inline]
+    pub fn position(&self) -> RecordPosition {
+        self.position
+    }
+
+    /// Index of the reference in the header, -1 if unmapped
+    #[inline]
+    pub fn ref_id(&self) -> i32 {
+        i32_at(self.data, 0)
+    }
+
+    #[inline]
+    pub fn next_ref_id(&self) -> i32 {
+        i32_at(self.data, 20)
+    }
+
+    #[inline]
+    pub fn bin(&self) -> u16 {
+        u16_at(self.data, 10)
+    }
+
+    #[inline]
+    fn read_name_len(&self) -> usize {
+        self.data[8] as usize
+    }
+
+    #[inline]
+    fn cigar_len(&self) -> usize {
+        u16_at(self.data, 12) as usize
+    }
+
+    #[inline]
+    pub fn seq_len(&self) -> usize {
+        i32_at(self.data, 16).max(0) as usize
+    }
+
+    #[inline]
+    fn cigar_start(&self) -> usize {
+        FIXED_SIZE + self.read_name_len()
+    }
+
+    #[inline]
+    fn seq_start(&self) -> usize {
+        self.cigar_start() + 4 * self.cigar_len()
+    }
+
+    #[inline]
+    fn qual_start(&self) -> usize {
+        self.seq_start() + self.seq_len().div_ceil(2)
+    }
+
+    #[inline]
+    pub fn cigar_ops(&self) -> impl Iterator<Item = CigarOp> + 'a {
+        let start = self.cigar_start();
+        self.data[start..start + 4 * self.cigar_len()]
+            .chunks_exact(4)
+            .map(|op| {
+                let op = u32::from_le_bytes([op[0], op[1], op[2], op[3]]);
+                CigarOp {
+                    op: *CIGAR_OPS.get((op & 0xf) as usize).unwrap_or(&b'?'),
+                    len: op >> 4,
+                }
+            })
+    }
+
+    /// Base at a position of the read
+    #[inline]
+    pub fn base(&self, index: usize) -> u8 {
+        let packed = self.data[self.seq_start() + index / 2];
+        let code = if index.is_multiple_of(2) { packed >> 4 } else { packed & 0xf };
+        BASES[code as usize]
+    }
+
+    /// Raw phred scores without offset, 0xff if qualities are missing
+    #[inline]
+    pub fn qual_raw(&self) -> &'a [u8] {
+        &self.data[self.qual_start()..self.qual_start() + self.seq_len()]
+    }
+
+    #[inline]
+    pub fn raw_tags(&self) -> &'a [u8] {
+        &self.data[self.qual_start() + self.seq_len()..]
+    }
+}
+
+/// Parse binary optional fields, errors are their reason
+fn parse_tags(mut data: &[u8]) -> Result<Vec<SamTag>, &'static str> {
+    fn take<'d>(data: &mut &'d [u8], n: usize) -> Result<&'d [u8], &'static str> {
+        if data.len() < n {
+            return Err("Truncated BAM optional field");
+        }
+        let (head, tail) = data.split_at(n);
+        *data = tail;
+        Ok(head)
+    }
+    fn take_string(data: &mut &[u8]) -> Result<Vec<u8>, &'static str> {
+        let end = memchr::memchr(0, data).ok_or("Unterminated BAM string field")?;
+        let value = data[..end].to_vec();
+        *data = &data[end + 1..];
+        Ok(value)
+    }
+    fn take_array<const N: usize, T>(data: &mut &[u8], count: usize, f: fn([u8; N]) -> T) -> Result<Vec<T>, &'static str> {
+        let values = take(data, count * N)?;
+        Ok(values
+            .chunks_exact(N)
+            .map(|v| f(v.try_into().expect("Chunk has array size")))
+            .collect())
+    }
+
+    let mut tags = Vec::new();
+    while !data.is_empty() {
+        let head = take(&mut data, 3)?;
+        let tag = [head[0], head[1]];
+        let value = match head[2] {
+            b'A' => TagValue::Char(take(&mut data, 1)?[0]),
+            b'c' => TagValue::Int(take(&mut data, 1)?[0] as i8 as i64),
+            b'C' => TagValue::Int(take(&mut data, 1)?[0] as i64),
+            b's' => TagValue::Int(take_array(&mut data, 1, i16::from_le_bytes)?[0] as i64),
+            b'S' => TagValue::Int(take_array(&mut data, 1, u16::from_le_bytes)?[0] as i64),
+            b'i' => TagValue::Int(take_array(&mut data, 1, i32::from_le_bytes)?[0] as i64),
+            b'I' => TagValue::Int(take_array(&mut data, 1, u32::from_le_bytes)?[0] as i64),
+            b'f' => TagValue::Float(take_array(&mut data, 1, f32::from_le_bytes)?[0]),
+            b'Z' => TagValue::String(take_string(&mut data)?),
+            b'H' => TagValue::Hex(take_string(&mut data)?),
+            b'B' => {
+                let head = take(&mut data, 5)?;
+                let count = u32::from_le_bytes([head[1], head[2], head[3], head[4]]) as usize;
+                let array = match head[0] {
+                    b'c' => TagArray::Int8(take_array(&mut data, count, i8::from_le_bytes)?),
+                    b'C' => TagArray::UInt8(take_array(&mut data, count, u8::from_le_bytes)?),
+                    b's' => TagArray::Int16(take_array(&mut data, count, i16::from_le_bytes)?),
+                    b'S' => TagArray::UInt16(take_array(&mut data, count, u16::from_le_bytes)?),
+                    b'i' => TagArray::Int32(take_array(&mut data, count, i32::from_le_bytes)?),
+                    b'I' => TagArray::UInt32(take_array(&mut data, count, u32::from_le_bytes)?),
+                    b'f' => TagArray::Float(take_array(&mut data, count, f32::from_le_bytes)?),
+                    _ => return Err("Invalid BAM array type"),
+                };
+                TagValue::Array(array)
+            }
+            _ => return Err("Invalid BAM optional field type"),
+        };
+        tags.push(SamTag::new(tag, value));
+    }
+    Ok(tags)
+}
+
+fn reference(header: &SamHeader, ref_id: i32) -> Option<&[u8]> {
+    let index = usize::try_from(ref_id).ok()?;
+    header.references.get(index).map(|reference| reference.name())
+}
+
+impl AlignmentRecord for RefBamRecord<'_> {
+    fn qname(&self) -> &[u8] {
+        // Without the NUL terminator
+        &self.data[FIXED_SIZE..FIXED_SIZE + self.read_name_len() - 1]
+    }
+
+    fn flag(&self) -> u16 {
+        u16_at(self.data, 14)
+    }
+
+    fn pos(&self) -> u32 {
+        (i32_at(self.data, 4) + 1).max(0) as u32
+    }
+
+    fn mapq(&self) -> u8 {
+        self.data[9]
+    }
+
+    fn pnext(&self) -> u32 {
+        (i32_at(self.data, 24) + 1).max(0) as u32
+    }
+
+    fn tlen(&self) -> i32 {
+        i32_at(self.data, 28)
+    }
+
+    fn reference_name<'a>(&'a self, header: &'a SamHeader) -> Option<&'a [u8]> {
+        reference(header, self.ref_id())
+    }
+
+    fn next_reference_name<'a>(&'a self, header: &'a SamHeader) -> Option<&'a [u8]> {
+        reference(header, self.next_ref_id())
+    }
+
+    fn cigar_string(&self) -> Cow<'_, [u8]> {
+        let mut cigar = Vec::new();
+        for op in self.cigar_ops() {
+            cigar.extend_from_slice(op.len.to_string().as_bytes());
+            cigar.push(op.op);
+        }
+        Cow::Owned(cigar)
+    }
+
+    fn sequence(&self) -> Cow<'_, [u8]> {
+        Cow::Owned((0..self.seq_len()).map(|i| self.base(i)).collect())
+    }
+
+    fn quality(&self) -> Cow<'_, [u8]> {
+        let qual = self.qual_raw();
+        match qual.first() {
+            None | Some(0xff) => Cow::Borrowed(&[]),
+            _ => Cow::Owned(qual.iter().map(|q| q.saturating_add(33)).collect()),
+        }
+    }
+
+    fn sam_tags(&self) -> Result<Vec<SamTag>, BioReaderError> {
+        parse_tags(self.raw_tags()).map_err(|reason| invalid(reason, self.position))
+    }
+}
@@ -1,3 +1,7 @@
+use std::fmt::{self, Display};
+
+use memchr::memchr;
+
 /// A FASTQ record that borrows data from a buffer
 #[derive(Debug, Clone)]
 
@@ -7,6 +11,12 @@ pub struct OwnedFastaRecord {
     pub sequence: Vec<u8>,
 }
 
+impl Default for OwnedFastaRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl OwnedFastaRecord { // Record for 
     pub fn new() -> Self {
         Self {
@@ -33,7 +43,7 @@ impl OwnedFastaRecord { // Record for
     #[inline]
     pub fn valid(&self) -> bool {
         let valid = self.seq().iter().all(|&c| c == b'A' || c == b'C' || c == b'G' || c == b'T' || c == b'N');
-        return valid
+        valid
     }
 
     #[inline]
@@ -46,18 +56,97 @@ impl OwnedFastaRecord { // Record for
             }
 
         );
-        return valid
+        valid
     }
 
     #[inline]
     pub fn perfect(&self) -> bool {
         let valid = self.seq().iter().all(|&c| c == b'A' || c == b'C' || c == b'G' || c == b'T');
-        return valid
+        valid
+    }
+}
+
+impl Display for OwnedFastaRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n{}",
+            std::str::from_utf8(self.head()).expect("Expect printable string"),
+            std::str::from_utf8(self.seq()).expect("Expect printable string"))
+    }
+}
+
+/// A FASTA record that borrows its header and sequence lines from the chunk buffer
+#[derive(Debug, Clone, Copy)]
+pub struct RefFastaRecord<'a> {
+    header: &'a [u8],
+    // Sequence lines including their line breaks
+    lines: &'a [u8],
+}
+
+impl<'a> RefFastaRecord<'a> {
+    pub(crate) fn new(header: &'a [u8], lines: &'a [u8]) -> Self {
+        Self { header, lines }
     }
 
-    pub fn to_string(&self) -> String {
-        let header = String::from_utf8(self.header.clone()).expect("Invalid UTF-8");
-        let sequence = String::from_utf8(self.sequence.clone()).expect("Invalid UTF-8");
-        format!("{}\n{}", header, sequence)
+    #[inline]
+    pub fn head(&self) -> &'a [u8] {
+        self.header
     }
-}
\ No newline at end of file
+
+    /// Lines of the sequence without their line breaks
+    #[inline]
+    pub fn seq_lines(&self) -> SeqLines<'a> {
+        SeqLines { rest: self.lines }
+    }
+
+    /// Length of the sequence without line breaks
+    pub fn seq_len(&self) -> usize {
+        self.seq_lines().map(<[u8]>::len).sum()
+    }
+
+    /// Append the sequence without line breaks to `sequence`
+    pub fn copy_seq_into(&self, sequence: &mut Vec<u8>) {
+        sequence.reserve(self.lines.len());
+        for line in self.seq_lines() {
+            sequence.extend_from_slice(line);
+        }
+    }
+
+    /// Overwrite `record` with a copy of this record, reusing its allocations
+    pub fn copy_into(&self, record: &mut OwnedFastaRecord) {
+        record.clear();
+        record.header.extend_from_slice(self.header);
+        self.copy_seq_into(&mut record.sequence);
+    }
+
+    /// Copy the record, e.g. to keep it beyond the current chunk or to get a contiguous sequence
+    pub fn to_owned(&self) -> OwnedFastaRecord {
+        let mut record = OwnedFastaRecord {
+            header: self.header.to_vec(),
+            sequence: Vec::new(),
+        };
+        self.copy_seq_into(&mut record.sequence);
+        record
+    }
+}
+
+/// Iterator over the sequence lines of a [`RefFastaRecord`]
+#[derive(Debug, Clone)]
+pub struct SeqLines<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for SeqLines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let (line, rest) = match memchr(b'\n', self.rest) {
+            Some(pos) => (&self.rest[..pos], &self.rest[pos + 1..]),
+            None => (self.rest, &self.rest[self.rest.len()..]),
+        };
+        self.rest = rest;
+        Some(line)
+    }
+}
@@ -1,8 +1,7 @@
 
-use core::slice::memchr;
-use std::{fmt::{self, Display}, u8};
+use std::fmt::{self, Display};
 use memchr::memchr;
-use colored::{Color, ColoredString, Colorize, CustomColor};
+use colored::{Colorize, CustomColor};
 
 use super::utils::reverse_complement_into_vec;
 
@@ -85,10 +84,42 @@ impl BufferPosition {
         trim_cr(&buffer[self.seq..self.sep - 1])
     }
 
+    /// Content of the separator line after the '+'
+    #[inline]
+    pub fn sep<'a>(&'a self, buffer: &'a [u8]) -> &'a [u8] {
+        trim_cr(&buffer[self.sep + 1..self.qual - 1])
+    }
+
     #[inline]
     pub fn qual<'a>(&'a self, buffer: &'a [u8]) -> &'a [u8] {
         trim_cr(&buffer[self.qual..self.pos.1])
     }
+
+    #[inline]
+    pub fn line_ending(&self, buffer: &[u8]) -> LineEnding {
+        if self.seq > 1 && buffer[self.seq - 2] == b'\r' {
+            LineEnding::Windows
+        } else {
+            LineEnding::Unix
+        }
+    }
+}
+
+/// Accessors shared by owned and borrowed FASTQ records
+pub trait FastqRecord {
+    fn head(&self) -> &[u8];
+    fn seq(&self) -> &[u8];
+    fn qual(&self) -> &[u8];
+
+    /// Content of the separator line after the '+', usually empty
+    fn sep(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Line ending of the input the record was read from, if known
+    fn line_ending(&self) -> Option<LineEnding> {
+        None
+    }
 }
 
 #[derive(Clone)]
@@ -120,6 +151,26 @@ impl OwnedFastqRecord {
     }
 }
 
+impl FastqRecord for OwnedFastqRecord {
+    fn head(&self) -> &[u8] {
+        &self.header
+    }
+
+    fn seq(&self) -> &[u8] {
+        &self.sequence
+    }
+
+    fn qual(&self) -> &[u8] {
+        &self.quality
+    }
+}
+
+impl Default for OwnedFastqRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Display for OwnedFastqRecord {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}\n{}\n+\n{}", 
@@ -132,7 +183,7 @@ impl Display for OwnedFastqRecord {
 pub fn qual_to_color(q: u8) -> CustomColor {
     match q {
         0..10 => CustomColor::new(255,0,255 - q*25),
-        10..20 => CustomColor::new(255,0 + (q-10)*25,0),
+        10..20 => CustomColor::new(255,(q-10)*25,0),
         20..30 => CustomColor::new(255 - (q-20)*25,255,0),
         _ => CustomColor::new(0,255,0),
     }
@@ -175,7 +226,7 @@ impl<'a> RefFastqRecord<'a> { // Record for
     pub fn valid(&self) -> bool {
         let mut valid = self.seq().len() == self.qual().len();
         valid &= self.seq().iter().all(|&c| c == b'A' || c == b'C' || c == b'G' || c == b'T' || c == b'N');
-        return valid
+        valid
     }
 
     #[inline]
@@ -188,18 +239,27 @@ impl<'a> RefFastqRecord<'a> { // Record for
             }
 
         );
-        return valid
+        valid
     }
 
     #[inline]
     pub fn perfect(&self) -> bool {
         let mut valid = self.seq().len() == self.qual().len();
         valid &= self.seq().iter().all(|&c| c == b'A' || c == b'C' || c == b'G' || c == b'T');
-        return valid
+        valid
+    }
+
+    /// Copy the record, e.g. to keep it beyond the current chunk
+    pub fn to_owned(&self) -> OwnedFastqRecord {
+        OwnedFastqRecord {
+            header: self.head().to_vec(),
+            sequence: self.seq().to_vec(),
+            quality: self.qual().to_vec(),
+        }
     }
 
     #[inline]
-    pub fn reverse_complement(&self, rec: &mut OwnedFastqRecord) -> () {
+    pub fn reverse_complement(&self, rec: &mut OwnedFastqRecord) {
         rec.header.clear();
         rec.sequence.clear();
         rec.quality.clear();
@@ -212,6 +272,28 @@ impl<'a> RefFastqRecord<'a> { // Record for
     }
 }
 
+impl FastqRecord for RefFastqRecord<'_> {
+    fn head(&self) -> &[u8] {
+        self.buf_pos.head(self.buffer)
+    }
+
+    fn seq(&self) -> &[u8] {
+        self.buf_pos.seq(self.buffer)
+    }
+
+    fn qual(&self) -> &[u8] {
+        self.buf_pos.qual(self.buffer)
+    }
+
+    fn sep(&self) -> &[u8] {
+        self.buf_pos.sep(self.buffer)
+    }
+
+    fn line_ending(&self) -> Option<LineEnding> {
+        Some(self.buf_pos.line_ending(self.buffer))
+    }
+}
+
 impl<'a> Display for RefFastqRecord<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}\n{}\n+\n{}", 
@@ -1,3 +1,5 @@
 pub mod fastq_record;
 pub mod fasta_record;
+pub mod sam_record;
+pub mod bam_record;
 pub mod utils;
\ No newline at end of file
@@ -0,0 +1,502 @@
+use std::{
+    borrow::Cow,
+    fmt::{self, Display},
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+use crate::{
+    error::{BioReaderError, RecordPosition},
+    sam_header::SamHeader,
+    sequence::fastq_record::LineEnding,
+};
+
+/// Number of mandatory fields of a SAM alignment line
+pub const MANDATORY_FIELDS: usize = 11;
+
+/// Accessors shared by SAM and BAM records.
+///
+/// Text fields are returned in their SAM representation, missing sequence,
+/// quality and CIGAR fields are returned empty instead of as `*`.
+pub trait AlignmentRecord {
+    fn qname(&self) -> &[u8];
+    fn flag(&self) -> u16;
+    /// 1-based leftmost mapping position, 0 if unmapped
+    fn pos(&self) -> u32;
+    fn mapq(&self) -> u8;
+    /// 1-based position of the mate, 0 if unavailable
+    fn pnext(&self) -> u32;
+    fn tlen(&self) -> i32;
+    fn reference_name<'a>(&'a self, header: &'a SamHeader) -> Option<&'a [u8]>;
+    fn next_reference_name<'a>(&'a self, header: &'a SamHeader) -> Option<&'a [u8]>;
+    fn cigar_string(&self) -> Cow<'_, [u8]>;
+    fn sequence(&self) -> Cow<'_, [u8]>;
+    /// Phred+33 encoded qualities
+    fn quality(&self) -> Cow<'_, [u8]>;
+    /// Optional fields parsed into typed values, invalid fields are reported with the position of the record
+    fn sam_tags(&self) -> Result<Vec<SamTag>, BioReaderError>;
+
+    /// Copy the record into an [`OwnedSamRecord`], resolving reference ids through the header
+    fn to_sam_record(&self, header: &SamHeader) -> Result<OwnedSamRecord, BioReaderError> {
+        let rname = self.reference_name(header).unwrap_or_default().to_vec();
+        let rnext = match self.next_reference_name(header) {
+            Some(name) if name == rname => b"=".to_vec(),
+            Some(name) => name.to_vec(),
+            None => Vec::new(),
+        };
+        Ok(OwnedSamRecord {
+            qname: self.qname().to_vec(),
+            flag: self.flag(),
+            rname,
+            pos: self.pos(),
+            mapq: self.mapq(),
+            cigar: self.cigar_string().into_owned(),
+            rnext,
+            pnext: self.pnext(),
+            tlen: self.tlen(),
+            seq: self.sequence().into_owned(),
+            qual: self.quality().into_owned(),
+            tags: self.sam_tags()?,
+        })
+    }
+}
+
+/// Represents the position of a SAM alignment line within a buffer.
+///
+/// Only offsets and the numeric fields are stored, text fields are sliced from
+/// the buffer on access.
+#[derive(Debug, Clone, Default)]
+pub struct SamPosition {
+    // (start, stop) of the line without the line ending
+    pub pos: (usize, usize),
+    // Exclusive end of every mandatory field, the next field starts one byte later
+    pub ends: [usize; MANDATORY_FIELDS],
+    pub flag: u16,
+    pub position: u32,
+    pub mapq: u8,
+    pub next_position: u32,
+    pub template_length: i32,
+    // The line ended with \r\n
+    pub crlf: bool,
+    // Location of the line in the input
+    pub record: RecordPosition,
+}
+
+impl SamPosition {
+    #[inline]
+    pub fn field<'a>(&self, buffer: &'a [u8], index: usize) -> &'a [u8] {
+        let start = match index {
+            0 => self.pos.0,
+            _ => self.ends[index - 1] + 1,
+        };
+        &buffer[start..self.ends[index]]
+    }
+
+    #[inline]
+    pub fn tags<'a>(&self, buffer: &'a [u8]) -> &'a [u8] {
+        let end = self.ends[MANDATORY_FIELDS - 1];
+        if end < self.pos.1 {
+            &buffer[end + 1..self.pos.1]
+        } else {
+            &[]
+        }
+    }
+}
+
+/// A SAM alignment that borrows data from a buffer
+#[derive(Debug, Clone)]
+pub struct RefSamRecord<'a> {
+    pub buffer: &'a [u8],
+    pub sam_pos: &'a SamPosition,
+}
+
+impl<'a> RefSamRecord<'a> {
+    /// The whole alignment line without the line ending
+    #[inline]
+    pub fn line(&self) -> &'a [u8] {
+        &self.buffer[self.sam_pos.pos.0..self.sam_pos.pos.1]
+    }
+
+    /// Location of the alignment line in the input
+    #[inline]
+    pub fn position(&self) -> RecordPosition {
+        self.sam_pos.record
+    }
+
+    /// Line ending of the input the record was read from
+    #[inline]
+    pub fn line_ending(&self) -> LineEnding {
+        match self.sam_pos.crlf {
+            true => LineEnding::Windows,
+            false => LineEnding::Unix,
+        }
+    }
+
+    #[inline]
+    pub fn qname(&self) -> &'a [u8] {
+        self.sam_pos.field(self.buffer, 0)
+    }
+
+    #[inline]
+    pub fn flag(&self) -> u16 {
+        self.sam_pos.flag
+    }
+
+    #[inline]
+    pub fn rname(&self) -> &'a [u8] {
+        self.sam_pos.field(self.buffer, 2)
+    }
+
+    /// 1-based leftmost mapping position, 0 if unmapped
+    #[inline]
+    pub fn pos(&self) -> u32 {
+        self.sam_pos.position
+    }
+
+    #[inline]
+    pub fn mapq(&self) -> u8 {
+        self.sam_pos.mapq
+    }
+
+    #[inline]
+    pub fn cigar(&self) -> &'a [u8] {
+        self.sam_pos.field(self.buffer, 5)
+    }
+
+    #[inline]
+    pub fn rnext(&self) -> &'a [u8] {
+        self.sam_pos.field(self.buffer, 6)
+    }
+
+    #[inline]
+    pub fn pnext(&self) -> u32 {
+        self.sam_pos.next_position
+    }
+
+    #[inline]
+    pub fn tlen(&self) -> i32 {
+        self.sam_pos.template_length
+    }
+
+    /// Sequence, `*` if not stored
+    #[inline]
+    pub fn seq(&self) -> &'a [u8] {
+        self.sam_pos.field(self.buffer, 9)
+    }
+
+    /// Phred+33 qualities, `*` if not stored
+    #[inline]
+    pub fn qual(&self) -> &'a [u8] {
+        self.sam_pos.field(self.buffer, 10)
+    }
+
+    /// Tab separated optional fields, empty if there are none
+    #[inline]
+    pub fn raw_tags(&self) -> &'a [u8] {
+        self.sam_pos.tags(self.buffer)
+    }
+
+    /// Optional fields in `TAG:TYPE:VALUE` form
+    #[inline]
+    pub fn tags(&self) -> impl Iterator<Item = &'a [u8]> {
+        let tags = self.raw_tags();
+        tags.split(|&c| c == b'\t').filter(|tag| !tag.is_empty())
+    }
+
+    /// Type and value of an optional field
+    #[inline]
+    pub fn tag(&self, name: &[u8; 2]) -> Option<(u8, &'a [u8])> {
+        self.tags()
+            .find(|tag| tag.len() >= 5 && &tag[..2] == name)
+            .map(|tag| (tag[3], &tag[5..]))
+    }
+
+    /// Optional fields parsed into typed values
+    #[inline]
+    pub fn parsed_tags(&self) -> impl Iterator<Item = Result<SamTag, BioReaderError>> + 'a {
+        let position = self.position();
+        self.tags().map(move |tag| {
+            SamTag::parse(tag).map_err(|err| BioReaderError::InvalidRecord { reason: err.to_string(), position })
+        })
+    }
+
+    #[inline]
+    pub fn is_unmapped(&self) -> bool {
+        self.flag() & 0x4 != 0
+    }
+
+    #[inline]
+    pub fn is_reverse(&self) -> bool {
+        self.flag() & 0x10 != 0
+    }
+
+    /// Copy the record into an [`OwnedSamRecord`] for editing, parsing all optional fields
+    pub fn to_owned_record(&self) -> Result<OwnedSamRecord, BioReaderError> {
+        Ok(OwnedSamRecord {
+            qname: self.qname().to_vec(),
+            flag: self.flag(),
+            rname: self.rname().to_vec(),
+            pos: self.pos(),
+            mapq: self.mapq(),
+            cigar: self.cigar().to_vec(),
+            rnext: self.rnext().to_vec(),
+            pnext: self.pnext(),
+            tlen: self.tlen(),
+            seq: self.seq().to_vec(),
+            qual: self.qual().to_vec(),
+            tags: self.parsed_tags().collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[inline]
+fn present(field: &[u8]) -> &[u8] {
+    match field {
+        b"*" => &[],
+        _ => field,
+    }
+}
+
+impl AlignmentRecord for RefSamRecord<'_> {
+    fn qname(&self) -> &[u8] {
+        self.sam_pos.field(self.buffer, 0)
+    }
+
+    fn flag(&self) -> u16 {
+        self.sam_pos.flag
+    }
+
+    fn pos(&self) -> u32 {
+        self.sam_pos.position
+    }
+
+    fn mapq(&self) -> u8 {
+        self.sam_pos.mapq
+    }
+
+    fn pnext(&self) -> u32 {
+        self.sam_pos.next_position
+    }
+
+    fn tlen(&self) -> i32 {
+        self.sam_pos.template_length
+    }
+
+    fn reference_name<'a>(&'a self, _header: &'a SamHeader) -> Option<&'a [u8]> {
+        Some(self.rname()).filter(|name| *name != b"*")
+    }
+
+    fn next_reference_name<'a>(&'a self, header: &'a SamHeader) -> Option<&'a [u8]> {
+        match self.rnext() {
+            b"*" => None,
+            b"=" => self.reference_name(header),
+            name => Some(name),
+        }
+    }
+
+    fn cigar_string(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(present(self.cigar()))
+    }
+
+    fn sequence(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(present(self.seq()))
+    }
+
+    fn quality(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(present(self.qual()))
+    }
+
+    fn sam_tags(&self) -> Result<Vec<SamTag>, BioReaderError> {
+        self.parsed_tags().collect()
+    }
+}
+
+/// Numeric array of a `B` optional field
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagArray {
+    Int8(Vec<i8>),
+    UInt8(Vec<u8>),
+    Int16(Vec<i16>),
+    UInt16(Vec<u16>),
+    Int32(Vec<i32>),
+    UInt32(Vec<u32>),
+    Float(Vec<f32>),
+}
+
+/// Value of an optional field
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagValue {
+    /// `A`, a single printable character
+    Char(u8),
+    /// `i`, stored in the smallest fitting type in BAM
+    Int(i64),
+    /// `f`
+    Float(f32),
+    /// `Z`
+    String(Vec<u8>),
+    /// `H`, hex digits of a byte array
+    Hex(Vec<u8>),
+    /// `B`
+    Array(TagArray),
+}
+
+/// Optional field of an alignment, e.g. `NM:i:1`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamTag {
+    pub tag: [u8; 2],
+    pub value: TagValue,
+}
+
+impl SamTag {
+    pub fn new(tag: [u8; 2], value: TagValue) -> Self {
+        Self { tag, value }
+    }
+
+    /// Parse an optional field in `TAG:TYPE:VALUE` form
+    pub fn parse(field: &[u8]) -> Result<Self, Error> {
+        if field.len() < 5 || field[2] != b':' || field[4] != b':' {
+            return Err(invalid_tag(field));
+        }
+        let raw = &field[5..];
+
+        let value = match field[3] {
+            b'A' if raw.len() == 1 => TagValue::Char(raw[0]),
+            b'i' => TagValue::Int(parse_number(raw).ok_or_else(|| invalid_tag(field))?),
+            b'f' => TagValue::Float(parse_number(raw).ok_or_else(|| invalid_tag(field))?),
+            b'Z' => TagValue::String(raw.to_vec()),
+            b'H' if raw.len().is_multiple_of(2) && raw.iter().all(u8::is_ascii_hexdigit) => {
+                TagValue::Hex(raw.to_vec())
+            }
+            b'B' if raw.len() == 1 || raw.get(1) == Some(&b',') => {
+                let values = raw[1..].split(|&c| c == b',').skip(1);
+                let array = match raw[0] {
+                    b'c' => parse_array(values).map(TagArray::Int8),
+                    b'C' => parse_array(values).map(TagArray::UInt8),
+                    b's' => parse_array(values).map(TagArray::Int16),
+                    b'S' => parse_array(values).map(TagArray::UInt16),
+                    b'i' => parse_array(values).map(TagArray::Int32),
+                    b'I' => parse_array(values).map(TagArray::UInt32),
+                    b'f' => parse_array(values).map(TagArray::Float),
+                    _ => None,
+                };
+                TagValue::Array(array.ok_or_else(|| invalid_tag(field))?)
+            }
+            _ => return Err(invalid_tag(field)),
+        };
+
+        Ok(Self {
+            tag: [field[0], field[1]],
+            value,
+        })
+    }
+
+    /// Append the field in `TAG:TYPE:VALUE` form
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.tag);
+        out.push(b':');
+        match &self.value {
+            TagValue::Char(c) => {
+                out.extend_from_slice(b"A:");
+                out.push(*c);
+            }
+            TagValue::Int(i) => {
+                out.extend_from_slice(b"i:");
+                out.extend_from_slice(i.to_string().as_bytes());
+            }
+            TagValue::Float(f) => {
+                out.extend_from_slice(b"f:");
+                out.extend_from_slice(f.to_string().as_bytes());
+            }
+            TagValue::String(s) => {
+                out.extend_from_slice(b"Z:");
+                out.extend_from_slice(s);
+            }
+            TagValue::Hex(h) => {
+                out.extend_from_slice(b"H:");
+                out.extend_from_slice(h);
+            }
+            TagValue::Array(array) => {
+                out.extend_from_slice(b"B:");
+                match array {
+                    TagArray::Int8(values) => write_array(out, b'c', values),
+                    TagArray::UInt8(values) => write_array(out, b'C', values),
+                    TagArray::Int16(values) => write_array(out, b's', values),
+                    TagArray::UInt16(values) => write_array(out, b'S', values),
+                    TagArray::Int32(values) => write_array(out, b'i', values),
+                    TagArray::UInt32(values) => write_array(out, b'I', values),
+                    TagArray::Float(values) => write_array(out, b'f', values),
+                }
+            }
+        }
+    }
+}
+
+#[inline]
+fn parse_number<N: FromStr>(raw: &[u8]) -> Option<N> {
+    std::str::from_utf8(raw).ok()?.parse().ok()
+}
+
+fn parse_array<'a, N: FromStr>(values: impl Iterator<Item = &'a [u8]>) -> Option<Vec<N>> {
+    values.map(parse_number).collect()
+}
+
+fn write_array<N: ToString>(out: &mut Vec<u8>, subtype: u8, values: &[N]) {
+    out.push(subtype);
+    for value in values {
+        out.push(b',');
+        out.extend_from_slice(value.to_string().as_bytes());
+    }
+}
+
+fn invalid_tag(field: &[u8]) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid SAM optional field '{}'", String::from_utf8_lossy(field)),
+    )
+}
+
+/// A SAM alignment that owns its data, e.g. to edit fields before writing it
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OwnedSamRecord {
+    pub qname: Vec<u8>,
+    pub flag: u16,
+    pub rname: Vec<u8>,
+    pub pos: u32,
+    pub mapq: u8,
+    pub cigar: Vec<u8>,
+    pub rnext: Vec<u8>,
+    pub pnext: u32,
+    pub tlen: i32,
+    pub seq: Vec<u8>,
+    pub qual: Vec<u8>,
+    pub tags: Vec<SamTag>,
+}
+
+impl OwnedSamRecord {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tag(&self, name: &[u8; 2]) -> Option<&TagValue> {
+        self.tags.iter().find(|tag| &tag.tag == name).map(|tag| &tag.value)
+    }
+
+    /// Replace the value of an optional field or append it if it is not present
+    pub fn set_tag(&mut self, name: [u8; 2], value: TagValue) {
+        match self.tags.iter_mut().find(|tag| tag.tag == name) {
+            Some(tag) => tag.value = value,
+            None => self.tags.push(SamTag::new(name, value)),
+        }
+    }
+
+    pub fn remove_tag(&mut self, name: &[u8; 2]) -> Option<TagValue> {
+        let index = self.tags.iter().position(|tag| &tag.tag == name)?;
+        Some(self.tags.remove(index).value)
+    }
+}
+
+impl Display for RefSamRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(self.line()))
+    }
+}
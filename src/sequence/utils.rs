@@ -1,5 +1,5 @@
 
-static COMPLEMENT: &'static [u8] = &[
+static COMPLEMENT: &[u8] = &[
     b'T', // A -> T
     b'V', // B -> V
     b'G', // C -> G
@@ -37,7 +37,7 @@ static COMPLEMENT: &'static [u8] = &[
 
 pub fn complement(base: u8) -> u8 {
     assert!(base > 64 && base < 91);
-    return COMPLEMENT[base as usize - 65]
+    COMPLEMENT[base as usize - 65]
 }
 
 pub fn reverse_complement_into_vec(from: &[u8], to: &mut Vec<u8>) {
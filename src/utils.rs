@@ -3,7 +3,9 @@ use std::{fs::File, io::Read, path::Path, time::{Duration, Instant}};
 const GZHEADER: [u8; 2] = [0x1f, 0x8b];
 const GZEXT: &str = ".gz";
 
-
+/// Whether a file has the gzip magic bytes and a `.gz` extension.
+///
+/// See [`crate::compression::Compression::detect`] for detection of all supported compressions.
 pub fn is_gzip(path: impl AsRef<Path>) -> Result<bool, std::io::Error> {
     let mut buf = [0u8, 0u8];
     let mut file = File::open(&path)?;
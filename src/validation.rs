@@ -0,0 +1,136 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::error::{BioReaderError, RecordPosition};
+
+/// Set of bytes allowed in a sequence
+#[derive(Debug, Clone)]
+pub struct Alphabet {
+    allowed: [bool; 256],
+}
+
+impl Alphabet {
+    pub const fn new(bytes: &[u8]) -> Self {
+        let mut allowed = [false; 256];
+        let mut i = 0;
+        while i < bytes.len() {
+            allowed[bytes[i] as usize] = true;
+            i += 1;
+        }
+        Self { allowed }
+    }
+
+    #[inline]
+    pub fn contains(&self, byte: u8) -> bool {
+        self.allowed[byte as usize]
+    }
+
+    /// First byte of the sequence that is not part of the alphabet
+    #[inline]
+    pub fn find_invalid(&self, seq: &[u8]) -> Option<u8> {
+        seq.iter().copied().find(|&c| !self.contains(c))
+    }
+}
+
+static DNA: Alphabet = Alphabet::new(b"ACGTN");
+static IUPAC: Alphabet = Alphabet::new(b"ACGTNRYKMSWBDHV");
+static RNA: Alphabet = Alphabet::new(b"ACGUN");
+static PROTEIN: Alphabet = Alphabet::new(b"ACDEFGHIKLMNPQRSTVWYBZXJUO*");
+
+/// Alphabet sequences are checked against, all alphabets are upper case
+#[derive(Debug, Clone, Default)]
+pub enum Validation {
+    None,
+    /// A, C, G, T and N
+    Dna,
+    /// DNA including the ambiguity codes R, Y, K, M, S, W, B, D, H and V
+    #[default]
+    Iupac,
+    /// A, C, G, U and N
+    Rna,
+    /// Amino acids including B, Z, X, J, U, O and the stop codon `*`
+    Protein,
+    Custom(Box<Alphabet>),
+}
+
+impl Validation {
+    /// Accept exactly the given bytes, e.g. `b"ACGTNacgtn"` for soft-masked reads
+    pub fn custom(bytes: &[u8]) -> Self {
+        Self::Custom(Box::new(Alphabet::new(bytes)))
+    }
+
+    pub fn alphabet(&self) -> Option<&Alphabet> {
+        match self {
+            Self::None => None,
+            Self::Dna => Some(&DNA),
+            Self::Iupac => Some(&IUPAC),
+            Self::Rna => Some(&RNA),
+            Self::Protein => Some(&PROTEIN),
+            Self::Custom(alphabet) => Some(alphabet),
+        }
+    }
+}
+
+/// What happens to records with bytes outside the alphabet
+#[derive(Debug, Clone, Default)]
+pub enum OnInvalid {
+    /// Stop reading and return [`BioReaderError::InvalidByte`]
+    #[default]
+    Error,
+    Skip,
+    /// Skip and add the number of skipped records (pairs for paired reads) to the counter
+    SkipAndCount(Arc<AtomicU64>),
+    /// Hand invalid records to the closure like valid ones
+    PassThrough,
+}
+
+/// Validation of records in the parallel readers
+#[derive(Debug, Clone, Default)]
+pub struct ValidationPolicy {
+    pub validation: Validation,
+    pub on_invalid: OnInvalid,
+}
+
+impl ValidationPolicy {
+    pub fn new(validation: Validation, on_invalid: OnInvalid) -> Self {
+        Self { validation, on_invalid }
+    }
+
+    /// Neither validate nor reject anything
+    pub fn none() -> Self {
+        Self::new(Validation::None, OnInvalid::PassThrough)
+    }
+
+    /// First invalid byte of a sequence, always `None` if invalid records are passed through
+    #[inline]
+    pub fn find_invalid(&self, seq: &[u8]) -> Option<u8> {
+        if let OnInvalid::PassThrough = self.on_invalid {
+            return None;
+        }
+        self.validation.alphabet()?.find_invalid(seq)
+    }
+
+    /// Like [`ValidationPolicy::find_invalid`] for a pair of reads, also returns the mate (1 or 2) with the invalid byte
+    #[inline]
+    pub fn find_invalid_pair(&self, seq1: &[u8], seq2: &[u8]) -> Option<(u8, usize)> {
+        self.find_invalid(seq1)
+            .map(|byte| (byte, 1))
+            .or_else(|| self.find_invalid(seq2).map(|byte| (byte, 2)))
+    }
+
+    /// Handle a record for which [`ValidationPolicy::find_invalid`] found `byte`.
+    ///
+    /// Returns an error if the record must not be skipped.
+    pub fn reject(&self, byte: u8, position: RecordPosition) -> Result<(), BioReaderError> {
+        match &self.on_invalid {
+            OnInvalid::Error => Err(BioReaderError::InvalidByte { byte, position }),
+            OnInvalid::SkipAndCount(counter) => {
+                counter.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            OnInvalid::Skip | OnInvalid::PassThrough => Ok(()),
+        }
+    }
+}